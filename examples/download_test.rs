@@ -1,14 +1,20 @@
 use cfspeedtest::speedtest::test_download;
 use cfspeedtest::OutputFormat;
+use cfspeedtest::SpeedTestCLIOptions;
+use cfspeedtest::Unit;
 
 fn main() {
     println!("Testing download speed with 10MB of payload");
 
-    let download_speed = test_download(
-        &reqwest::blocking::Client::new(),
-        10_000_000,
-        OutputFormat::None, // don't write to stdout while running the test
-    );
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::None, // don't write to stdout while running the test
+        ..Default::default()
+    };
+    let download_speed = test_download(&reqwest::blocking::Client::new(), 10_000_000, &options)
+        .expect("download test failed");
 
-    println!("download speed in mbit: {download_speed}")
+    println!(
+        "download speed in mbit: {}",
+        Unit::Mbps.convert(download_speed)
+    )
 }
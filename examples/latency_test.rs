@@ -1,19 +1,24 @@
 use cfspeedtest::speedtest::run_latency_test;
 use cfspeedtest::OutputFormat;
+use cfspeedtest::SpeedTestCLIOptions;
 
 fn main() {
     println!("Testing latency");
 
     let (latency_results, avg_latency) = run_latency_test(
         &reqwest::blocking::Client::new(),
+        &SpeedTestCLIOptions::default().server_url,
         25,
         OutputFormat::None, // don't write to stdout while running the test
-    );
+    )
+    .expect("latency test failed");
 
-    println!("average latancy in ms: {avg_latency}");
+    if let Some(avg_latency) = avg_latency {
+        println!("average latancy in ms: {avg_latency}");
+    }
 
     println!("all latency test results");
-    for latency_result in latency_results {
-        println!("latency in ms: {latency_result}");
+    for (latency, timestamp) in latency_results {
+        println!("latency in ms: {latency} at {timestamp}");
     }
 }
@@ -17,10 +17,21 @@ fn main() {
         max_payload_size: PayloadSize::M10,
         disable_dynamic_max_payload_size: false,
         timeout_secs: Some(30),
+        ..Default::default()
     };
 
-    let measurements = speed_test(reqwest::blocking::Client::new(), options);
-    measurements
+    let result = speed_test(reqwest::blocking::Client::new(), options).expect("speed test failed");
+    if let Some(avg_latency) = result.avg_latency {
+        println!("average latency in ms: {avg_latency}");
+    }
+    if let Some(server_location) = result.server_location {
+        println!("server location: {server_location}");
+    }
+    if let Some(client_ip) = result.client_ip {
+        println!("client ip: {client_ip}");
+    }
+    result
+        .measurements
         .iter()
         .for_each(|measurement| println!("{measurement}"));
 }
@@ -0,0 +1,161 @@
+use crate::error::SpeedTestError;
+use crate::measurements::{JsonResult, ThroughputStats, JSON_SCHEMA_VERSION};
+use crate::speedtest::{SpeedTestResult, TestType};
+use crate::thresholds::best_avg_mbps;
+use crate::{OutputFormat, SpeedTestCLIOptions, Unit};
+use chrono::{DateTime, Utc};
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+
+/// Metrics of a previously saved json result, see `--baseline`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Baseline {
+    /// Completion time of the baseline run
+    pub timestamp: DateTime<Utc>,
+    /// Best avg download throughput of all payload sizes in mbit/s
+    pub download_mbps: Option<f64>,
+    /// Best avg upload throughput of all payload sizes in mbit/s
+    pub upload_mbps: Option<f64>,
+    pub latency_ms: Option<f64>,
+}
+
+/// Metric compared against the baseline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    Download,
+    Upload,
+    Latency,
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{self:?}").to_lowercase())
+    }
+}
+
+/// Change of a metric compared to the baseline, throughput is in mbit/s and latency in ms
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BaselineDelta {
+    pub metric: Metric,
+    pub current: f64,
+    pub baseline: f64,
+}
+
+impl BaselineDelta {
+    /// Relative change in percent of the baseline, positive if the metric increased
+    pub fn delta_percent(&self) -> f64 {
+        (self.current - self.baseline) / self.baseline * 100.0
+    }
+
+    /// Returns by how many percent the metric got worse, i.e. the throughput dropped or the
+    /// latency rose, negative if it improved
+    pub fn regression_percent(&self) -> f64 {
+        match self.metric {
+            Metric::Download | Metric::Upload => -self.delta_percent(),
+            Metric::Latency => self.delta_percent(),
+        }
+    }
+
+    /// Formats the delta as e.g. "download: 210.00 mbit/s (+4.0% vs baseline)"
+    pub fn format(&self, unit: Unit) -> String {
+        let current = match self.metric {
+            Metric::Download | Metric::Upload => {
+                unit.format(Unit::Mbps.to_bytes_per_sec(self.current))
+            }
+            Metric::Latency => format!("{:.2} ms", self.current),
+        };
+        format!(
+            "{}: {current} ({:+.1}% vs baseline)",
+            self.metric,
+            self.delta_percent()
+        )
+    }
+}
+
+/// Reads the baseline from a json result written with `--output-format json`. If the file
+/// contains multiple runs written with `--append`, the last one is used. A warning is printed
+/// if the baseline was written with a different json schema version
+pub fn load_baseline(path: &Path) -> Result<Baseline, SpeedTestError> {
+    let invalid =
+        |message: String| SpeedTestError::Baseline(format!("{}: {message}", path.display()));
+    let content = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+    let last_run = serde_json::Deserializer::from_str(&content)
+        .into_iter::<serde_json::Value>()
+        .last()
+        .ok_or_else(|| invalid("no json result found".to_string()))?
+        .map_err(|e| invalid(e.to_string()))?;
+    let schema_version = last_run["schema_version"].as_u64();
+    if schema_version != Some(JSON_SCHEMA_VERSION.into()) {
+        eprintln!(
+            "WARNING: baseline {} was written with json schema version {}, expected {JSON_SCHEMA_VERSION}",
+            path.display(),
+            schema_version.map_or("N/A".to_string(), |v| v.to_string())
+        );
+    }
+    let result: JsonResult = serde_json::from_value(last_run)
+        .map_err(|e| invalid(format!("{e}, the json schema version may not be supported")))?;
+    Ok(Baseline {
+        timestamp: result.timestamp,
+        download_mbps: result.download.as_ref().and_then(best_avg_mbps_of),
+        upload_mbps: result.upload.as_ref().and_then(best_avg_mbps_of),
+        latency_ms: result.latency.map(|latency| latency.avg),
+    })
+}
+
+fn best_avg_mbps_of(stats: &ThroughputStats) -> Option<f64> {
+    stats
+        .payload_sizes
+        .iter()
+        .map(|size| Unit::Mbps.convert(stats.unit.to_bytes_per_sec(size.avg)))
+        .reduce(f64::max)
+}
+
+/// Compares the result against the baseline. Metrics missing in either of them are skipped
+pub fn compare(result: &SpeedTestResult, baseline: &Baseline) -> Vec<BaselineDelta> {
+    let current_latency = result.avg_latency;
+    [
+        (
+            Metric::Download,
+            best_avg_mbps(result, TestType::Download),
+            baseline.download_mbps,
+        ),
+        (
+            Metric::Upload,
+            best_avg_mbps(result, TestType::Upload),
+            baseline.upload_mbps,
+        ),
+        (Metric::Latency, current_latency, baseline.latency_ms),
+    ]
+    .into_iter()
+    .filter_map(|(metric, current, baseline)| match (current, baseline) {
+        (Some(current), Some(baseline)) if baseline > 0.0 => Some(BaselineDelta {
+            metric,
+            current,
+            baseline,
+        }),
+        _ => None,
+    })
+    .collect()
+}
+
+/// Prints the comparison with the baseline, to stderr if stdout is used for another output
+/// format
+pub fn print_comparison(
+    deltas: &[BaselineDelta],
+    baseline: &Baseline,
+    options: &SpeedTestCLIOptions,
+) {
+    let mut lines = vec![format!(
+        "\nComparison with baseline from {}",
+        baseline.timestamp.format("%Y-%m-%d %H:%M:%S UTC")
+    )];
+    lines.extend(deltas.iter().map(|delta| delta.format(options.unit)));
+    for line in lines {
+        if options.output_format == OutputFormat::StdOut {
+            println!("{line}");
+        } else {
+            eprintln!("{line}");
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::error::SpeedTestError;
+use reqwest::blocking::Client;
+use reqwest::Url;
+use serde::Deserialize;
+use std::net::IpAddr;
+
+/// DNS-over-HTTPS endpoints of Cloudflare's 1.1.1.1 resolver, addressed by IP so the lookup
+/// doesn't depend on the system resolver
+pub const RESOLVER_URL_V4: &str = "https://1.1.1.1/dns-query";
+pub const RESOLVER_URL_V6: &str = "https://[2606:4700:4700::1111]/dns-query";
+
+const RECORD_TYPE_A: u16 = 1;
+const RECORD_TYPE_AAAA: u16 = 28;
+
+/// Response of the DoH JSON API, see
+/// https://developers.cloudflare.com/1.1.1.1/encryption/dns-over-https/make-api-requests/dns-json/
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+/// Returns the resolver URL reachable with the address family forced by `--ipv6`
+pub fn resolver_url(ipv6: bool) -> &'static str {
+    if ipv6 {
+        RESOLVER_URL_V6
+    } else {
+        RESOLVER_URL_V4
+    }
+}
+
+/// Resolves `host` using the DoH resolver at `resolver_url`. `ipv4` and `ipv6` restrict the
+/// lookup to A or AAAA records, otherwise the A records are returned before the AAAA records
+pub fn resolve(
+    client: &Client,
+    resolver_url: &str,
+    host: &str,
+    ipv4: bool,
+    ipv6: bool,
+) -> Result<Vec<IpAddr>, SpeedTestError> {
+    let mut addresses = Vec::new();
+    if !ipv6 {
+        addresses.extend(query(client, resolver_url, host, RECORD_TYPE_A)?);
+    }
+    if !ipv4 {
+        addresses.extend(query(client, resolver_url, host, RECORD_TYPE_AAAA)?);
+    }
+    if addresses.is_empty() {
+        return Err(SpeedTestError::Dns(format!("no address found for {host}")));
+    }
+    Ok(addresses)
+}
+
+fn query(
+    client: &Client,
+    resolver_url: &str,
+    host: &str,
+    record_type: u16,
+) -> Result<Vec<IpAddr>, SpeedTestError> {
+    let type_name = if record_type == RECORD_TYPE_A {
+        "A"
+    } else {
+        "AAAA"
+    };
+    let url = Url::parse_with_params(resolver_url, &[("name", host), ("type", type_name)])
+        .map_err(|e| SpeedTestError::Dns(format!("invalid resolver URL {resolver_url}: {e}")))?;
+    let body = client
+        .get(url)
+        .header("accept", "application/dns-json")
+        .send()?
+        .error_for_status()?
+        .text()?;
+    let response: DohResponse = serde_json::from_str(&body)
+        .map_err(|e| SpeedTestError::InvalidResponse(format!("unexpected DoH response: {e}")))?;
+    if response.status != 0 {
+        return Err(SpeedTestError::Dns(format!(
+            "{type_name} lookup of {host} returned DNS status {}",
+            response.status
+        )));
+    }
+    // CNAME records in the answer are followed by the resolver and can be skipped
+    Ok(response
+        .answer
+        .iter()
+        .filter(|answer| answer.record_type == record_type)
+        .filter_map(|answer| answer.data.parse().ok())
+        .collect())
+}
@@ -0,0 +1,99 @@
+use std::{error::Error, fmt, io};
+
+/// Errors that can occur while running a speed test
+#[derive(Debug)]
+pub enum SpeedTestError {
+    /// The reqwest client could not be built from the given options
+    ClientBuild(reqwest::Error),
+    /// A request to the speed test server failed
+    Request(reqwest::Error),
+    /// A request to the speed test server timed out
+    Timeout,
+    /// The speed test server returned a response that could not be interpreted
+    InvalidResponse(String),
+    /// The requested network interface doesn't exist or has no usable address
+    Interface(String),
+    /// The DNS-over-HTTPS lookup of the speed test server failed
+    Dns(String),
+    /// The options are invalid or not supported by the speed test variant
+    InvalidOptions(String),
+    /// The baseline file could not be read or doesn't contain a json result
+    Baseline(String),
+    /// Writing the results failed
+    Io(io::Error),
+}
+
+impl fmt::Display for SpeedTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ClientBuild(e) => write!(f, "failed to initialize reqwest client: {e}"),
+            Self::Request(e) => write!(f, "request failed: {e}"),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::InvalidResponse(message) => write!(f, "invalid response: {message}"),
+            Self::Interface(message) => write!(f, "invalid interface: {message}"),
+            Self::Dns(message) => write!(f, "DoH lookup failed: {message}"),
+            Self::InvalidOptions(message) => write!(f, "invalid options: {message}"),
+            Self::Baseline(message) => write!(f, "failed to load baseline {message}"),
+            Self::Io(e) => write!(f, "io error: {e}"),
+        }
+    }
+}
+
+impl SpeedTestError {
+    /// Returns whether the failed request may succeed if retried, e.g. after a timeout,
+    /// connection reset or server error. Client errors like 4xx responses aren't retryable
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Timeout => true,
+            Self::Request(e) => match e.status() {
+                Some(status) => status.is_server_error(),
+                None => e.is_connect() || e.is_request() || e.is_body(),
+            },
+            Self::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            Self::ClientBuild(_)
+            | Self::InvalidResponse(_)
+            | Self::Interface(_)
+            | Self::Dns(_)
+            | Self::InvalidOptions(_)
+            | Self::Baseline(_) => false,
+        }
+    }
+}
+
+impl Error for SpeedTestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ClientBuild(e) | Self::Request(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::Timeout
+            | Self::InvalidResponse(_)
+            | Self::Interface(_)
+            | Self::Dns(_)
+            | Self::InvalidOptions(_)
+            | Self::Baseline(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SpeedTestError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout
+        } else {
+            Self::Request(e)
+        }
+    }
+}
+
+impl From<io::Error> for SpeedTestError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
@@ -0,0 +1,51 @@
+/// Block characters used for the bars, from lowest to highest
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Number of buckets the throughput samples are grouped into in the summary
+pub(crate) const HISTOGRAM_BUCKETS: usize = 20;
+
+/// Counts the samples in `nr_buckets` buckets of equal width between the min and max sample.
+/// The max sample is counted in the last bucket, all samples are counted in the first bucket if
+/// they're equal. Returns an empty `Vec` if there are no samples or buckets
+pub fn bucket_counts(samples: &[f64], nr_buckets: usize) -> Vec<usize> {
+    if samples.is_empty() || nr_buckets == 0 {
+        return Vec::new();
+    }
+    let min = samples.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = samples.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let width = (max - min) / nr_buckets as f64;
+    let mut counts = vec![0; nr_buckets];
+    for sample in samples {
+        let bucket = if width > 0.0 {
+            (((sample - min) / width) as usize).min(nr_buckets - 1)
+        } else {
+            0
+        };
+        counts[bucket] += 1;
+    }
+    counts
+}
+
+/// Renders the bucket counts as a sparkline of block characters scaled to the largest bucket,
+/// empty buckets are rendered as spaces
+pub fn render_sparkline(counts: &[usize]) -> String {
+    let max_count = counts.iter().copied().max().unwrap_or_default();
+    counts
+        .iter()
+        .map(|&count| match count {
+            0 => ' ',
+            _ => BLOCKS[(count * BLOCKS.len()).div_ceil(max_count) - 1],
+        })
+        .collect()
+}
+
+/// Renders a histogram of the samples with the min and max sample as labels, e.g.
+/// "[▁ ▃█▂  ▁] 90.12 .. 104.56"
+pub fn render_histogram(samples: &[f64], nr_buckets: usize) -> String {
+    let min = samples.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = samples.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    format!(
+        "[{}] {min:.2} .. {max:.2}",
+        render_sparkline(&bucket_counts(samples, nr_buckets))
+    )
+}
@@ -0,0 +1,67 @@
+use crate::error::SpeedTestError;
+use crate::SpeedTestCLIOptions;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Returns the unspecified address of the family forced by `--ipv4` or `--ipv6` to bind the
+/// client to, or `None` if no family is forced
+pub fn local_bind_address(options: &SpeedTestCLIOptions) -> Option<IpAddr> {
+    if options.ipv4 {
+        Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    } else if options.ipv6 {
+        Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED))
+    } else {
+        None
+    }
+}
+
+/// Resolves the local address of the network interface with the given name.
+/// `ipv4` and `ipv6` restrict the address family, otherwise IPv4 addresses are preferred.
+/// IPv6 link-local addresses are skipped as they can't be used to reach the speed test server
+pub fn interface_address(name: &str, ipv4: bool, ipv6: bool) -> Result<IpAddr, SpeedTestError> {
+    let interfaces = if_addrs::get_if_addrs().map_err(|e| {
+        SpeedTestError::Interface(format!("failed to list network interfaces: {e}"))
+    })?;
+    let addresses: Vec<IpAddr> = interfaces
+        .iter()
+        .filter(|interface| interface.name == name)
+        .map(|interface| interface.ip())
+        .filter(|ip| !is_ipv6_link_local(ip))
+        .collect();
+    if addresses.is_empty() && !interfaces.iter().any(|interface| interface.name == name) {
+        let mut names: Vec<&str> = interfaces.iter().map(|i| i.name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        return Err(SpeedTestError::Interface(format!(
+            "no network interface named '{name}', available interfaces: {}",
+            names.join(", ")
+        )));
+    }
+
+    let address = if ipv4 {
+        addresses.iter().find(|ip| ip.is_ipv4())
+    } else if ipv6 {
+        addresses.iter().find(|ip| ip.is_ipv6())
+    } else {
+        addresses
+            .iter()
+            .find(|ip| ip.is_ipv4())
+            .or_else(|| addresses.first())
+    };
+    address.copied().ok_or_else(|| {
+        let family = match (ipv4, ipv6) {
+            (true, _) => "IPv4 ",
+            (_, true) => "IPv6 ",
+            _ => "",
+        };
+        SpeedTestError::Interface(format!(
+            "network interface '{name}' has no usable {family}address"
+        ))
+    })
+}
+
+fn is_ipv6_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+        IpAddr::V4(_) => false,
+    }
+}
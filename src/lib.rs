@@ -1,18 +1,35 @@
+pub mod baseline;
 pub mod boxplot;
+pub mod doh;
+pub mod error;
+pub mod histogram;
+pub mod interface;
 pub mod measurements;
+pub mod payload;
 pub mod progress;
+pub mod rate_limit;
 pub mod speedtest;
+#[cfg(feature = "async")]
+pub mod speedtest_async;
+pub mod stats;
+pub mod thresholds;
 use std::fmt;
 use std::fmt::Display;
+use std::path::PathBuf;
 
 use clap::Parser;
-use speedtest::PayloadSize;
+use serde::{Deserialize, Serialize};
+use speedtest::{PayloadSize, DEFAULT_BASE_URL, DEFAULT_READ_CHUNK_BYTES, MAX_READ_CHUNK_BYTES};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OutputFormat {
     Csv,
     Json,
     JsonPretty,
+    InfluxLineProtocol,
+    Prometheus,
+    /// Single `key=value` line for shell scripts, see `measurements::format_summary`
+    Summary,
     StdOut,
     None,
 }
@@ -29,37 +46,183 @@ impl OutputFormat {
             "csv" => Ok(Self::Csv),
             "json" => Ok(Self::Json),
             "json_pretty" | "json-pretty" => Ok(Self::JsonPretty),
+            "influx" => Ok(Self::InfluxLineProtocol),
+            "prometheus" => Ok(Self::Prometheus),
+            "summary" => Ok(Self::Summary),
             "stdout" => Ok(Self::StdOut),
-            _ => Err("Value needs to be one of csv, json or json-pretty".to_string()),
+            _ => Err(
+                "Value needs to be one of csv, json, json-pretty, influx, prometheus or summary"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// Unit used to display throughput, measurements are always kept in bytes/s internally
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    /// Megabits per second
+    Mbps,
+    /// Megabytes per second
+    MBytePs,
+    /// Gigabits per second
+    Gbps,
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl Unit {
+    pub fn from(unit_string: String) -> Result<Self, String> {
+        match unit_string.to_lowercase().as_str() {
+            "mbps" => Ok(Self::Mbps),
+            "mbyteps" => Ok(Self::MBytePs),
+            "gbps" => Ok(Self::Gbps),
+            _ => Err("Value needs to be one of mbps, mbyteps or gbps".to_string()),
+        }
+    }
+
+    /// Converts a throughput in bytes/s to this unit
+    pub fn convert(&self, bytes_per_sec: f64) -> f64 {
+        match self {
+            Self::Mbps => bytes_per_sec * 8.0 / 1_000_000.0,
+            Self::MBytePs => bytes_per_sec / 1_000_000.0,
+            Self::Gbps => bytes_per_sec * 8.0 / 1_000_000_000.0,
+        }
+    }
+
+    /// Converts a throughput in this unit back to bytes/s
+    pub fn to_bytes_per_sec(&self, value: f64) -> f64 {
+        value / self.convert(1.0)
+    }
+
+    /// Short label of this unit, e.g. "mbit/s"
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Mbps => "mbit/s",
+            Self::MBytePs => "MB/s",
+            Self::Gbps => "gbit/s",
+        }
+    }
+
+    /// Formats a throughput in bytes/s in this unit, e.g. "123.45 mbit/s"
+    pub fn format(&self, bytes_per_sec: f64) -> String {
+        format!("{:.2} {}", self.convert(bytes_per_sec), self.label())
+    }
+}
+
+/// HTTP version used for all requests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpVersion {
+    Http1,
+    Http2,
+}
+
+impl Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Http1 => write!(f, "1.1"),
+            Self::Http2 => write!(f, "2"),
+        }
+    }
+}
+
+impl HttpVersion {
+    pub fn from(http_version_string: String) -> Result<Self, String> {
+        match http_version_string.as_str() {
+            "1.1" => Ok(Self::Http1),
+            "2" => Ok(Self::Http2),
+            _ => Err("Value needs to be one of 1.1 or 2".to_string()),
+        }
+    }
+}
+
+/// IP address family a speed test was forced to with `--ipv4`, `--ipv6` or `--dual-stack`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl IpFamily {
+    /// Human readable name, e.g. "IPv4"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ipv4 => "IPv4",
+            Self::Ipv6 => "IPv6",
         }
     }
 }
 
 /// Unofficial CLI for speed.cloudflare.com
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct SpeedTestCLIOptions {
-    /// Number of test runs per payload size. Needs to be at least 4
-    #[arg(value_parser = clap::value_parser!(u32).range(4..1000), short, long, default_value_t = 10)]
+    /// Number of test runs per payload size. Needs to be at least 4. The summary shows the
+    /// aggregate of all runs, json output additionally lists every single run
+    #[arg(
+        value_parser = clap::value_parser!(u32).range(4..1000),
+        short,
+        long,
+        visible_alias = "runs-per-size",
+        default_value_t = 10
+    )]
     pub nr_tests: u32,
 
+    /// Warn that the results may be unreliable if the standard deviation of the throughput of a
+    /// payload size exceeds the given percent of its mean. json output sets `unstable` instead
+    #[arg(long, value_name = "PERCENT", default_value_t = 25.0)]
+    pub max_variation: f64,
+
     /// Number of latency tests to run
     #[arg(long, default_value_t = 25)]
     pub nr_latency_tests: u32,
 
-    /// The max payload size in bytes to use [100k, 1m, 10m, 25m or 100m]
+    /// The max payload size in bytes to use [e.g. 100k, 1m, 10m, 25m or 100m]
     #[arg(value_parser = parse_payload_size, short, long, default_value_t = PayloadSize::M25)]
     pub max_payload_size: PayloadSize,
 
-    /// Set the output format [csv, json or json-pretty] >
+    /// Comma-separated list of payload sizes to use instead of the defaults [e.g. 1MB,10MB,100MB]
+    #[arg(
+        long,
+        value_parser = parse_payload_size,
+        value_delimiter = ',',
+        conflicts_with = "max_payload_size"
+    )]
+    pub payload_sizes: Option<Vec<PayloadSize>>,
+
+    /// Set the output format [csv, json, json-pretty, influx, prometheus or summary] >
     /// This silences all other output to stdout
     #[arg(value_parser = parse_output_format, short, long, default_value_t = OutputFormat::StdOut)]
     pub output_format: OutputFormat,
 
-    /// Enable verbose output i.e. print boxplots of the measurements
+    /// Set the throughput unit [mbps, mbyteps or gbps]
+    #[arg(value_parser = parse_unit, short, long, default_value_t = Unit::Mbps)]
+    pub unit: Unit,
+
+    /// Enable verbose output i.e. print every single request and boxplots of the measurements
     #[arg(short, long)]
     pub verbose: bool,
 
+    /// Only print the summary, i.e. no banner, metadata or progress
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Render a histogram of the throughput samples of every payload size in the summary
+    #[arg(long)]
+    pub histogram: bool,
+
     /// Force usage of IPv4
     #[arg(long)]
     pub ipv4: bool,
@@ -68,11 +231,41 @@ pub struct SpeedTestCLIOptions {
     #[arg(long)]
     pub ipv6: bool,
 
+    /// Run the speed test once over IPv4 and once over IPv6 to compare both address families.
+    /// json output nests the results under `ipv4` and `ipv6` keys
+    #[arg(long, conflicts_with_all = ["ipv4", "ipv6", "watch", "baseline"])]
+    pub dual_stack: bool,
+
+    /// Bind to the local address of the given network interface [e.g. eth0 or wlan0].
+    /// Use --ipv4 or --ipv6 to select the address family
+    #[arg(long, value_name = "NAME")]
+    pub interface: Option<String>,
+
+    /// Resolve the host of the --server-url via DNS-over-HTTPS using Cloudflare's 1.1.1.1
+    /// resolver instead of the system resolver. Falls back to the system resolver if the lookup
+    /// fails. Use --ipv4 or --ipv6 to only resolve A or AAAA records
+    #[arg(long)]
+    pub doh: bool,
+
     /// Disables dynamically skipping tests with larger payload sizes if the tests for the previous payload
     /// size took longer than 5 seconds
     #[arg(short, long)]
     pub disable_dynamic_max_payload_size: bool,
 
+    /// Number of warm-up downloads with the smallest payload size before the measured runs.
+    /// Warm-up downloads aren't included in any output
+    #[arg(long, default_value_t = 0)]
+    pub warmup: u32,
+
+    /// Stop testing larger payload sizes once the avg throughput of consecutive payload sizes
+    /// converged
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// Relative tolerance in percent used to detect convergence in adaptive mode
+    #[arg(long, default_value_t = 5.0, requires = "adaptive")]
+    pub adaptive_tolerance: f64,
+
     /// Test download speed only
     #[arg(long, conflicts_with = "upload_only")]
     pub download_only: bool,
@@ -81,20 +274,252 @@ pub struct SpeedTestCLIOptions {
     #[arg(long, conflicts_with = "download_only")]
     pub upload_only: bool,
 
-    /// Custom timeout
+    /// Test latency only, skipping all download and upload tests
+    #[arg(long, conflicts_with_all = ["download_only", "upload_only"])]
+    pub latency_only: bool,
+
+    /// Skip the latency test and only test download and upload speed
+    #[arg(long, conflicts_with = "latency_only")]
+    pub no_latency: bool,
+
+    /// Measure packet loss as the share of a burst of small requests which fail or exceed the
+    /// --loss-timeout-ms deadline
+    #[arg(long)]
+    pub measure_loss: bool,
+
+    /// Number of requests sent to measure packet loss
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        default_value_t = 100,
+        requires = "measure_loss"
+    )]
+    pub loss_requests: u32,
+
+    /// Deadline of every single packet loss request in ms, slower requests are counted as lost
+    #[arg(
+        long,
+        value_name = "MS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        default_value_t = 500,
+        requires = "measure_loss"
+    )]
+    pub loss_timeout_ms: u64,
+
+    /// Timeout of every request in seconds. Downloads and uploads use it as the minimum of a
+    /// timeout scaled with the payload size [default: 30]
     #[arg(short, long)]
     pub timeout_secs: Option<u64>,
+
+    /// Timeout of every download in seconds instead of the one scaled with the payload size
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub download_timeout: Option<u64>,
+
+    /// Timeout of every upload in seconds instead of the one scaled with the payload size
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub upload_timeout: Option<u64>,
+
+    /// Stop starting new download and upload measurements once the speed test ran for the given
+    /// number of seconds and report the measurements completed so far
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_duration: Option<u64>,
+
+    /// Retry failed download and upload requests up to the given number of times with
+    /// exponential backoff. Only timeouts, connection errors and server errors are retried
+    #[arg(long, default_value_t = 0)]
+    pub retries: u32,
+
+    /// Number of parallel connections used for each download and upload test
+    #[arg(value_parser = clap::value_parser!(u32).range(1..), long, default_value_t = 1)]
+    pub parallel: u32,
+
+    /// Seed of the pseudo-random upload payload to make it reproducible. Without a seed the
+    /// payload is generated from OS entropy
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Limit the throughput of the downloads and uploads to the given mbit/s to avoid saturating
+    /// a shared link. The results then don't reflect the capacity of the connection
+    #[arg(long, value_name = "MBPS")]
+    pub max_mbps: Option<f64>,
+
+    /// Size of the buffer in bytes the download response is read into [e.g. 16k or 1m, at most
+    /// 16MiB]. Larger buffers reduce the syscall overhead on fast links with a high latency
+    #[arg(long, value_name = "BYTES", value_parser = parse_read_chunk_bytes, default_value_t = DEFAULT_READ_CHUNK_BYTES)]
+    pub read_chunk_bytes: usize,
+
+    /// Force the HTTP version [1.1 or 2] instead of negotiating it with the server
+    #[arg(long, value_parser = parse_http_version)]
+    pub http_version: Option<HttpVersion>,
+
+    /// User-Agent header sent with every request
+    #[arg(long, default_value = concat!("cfspeedtest/", env!("CARGO_PKG_VERSION")))]
+    pub user_agent: String,
+
+    /// Accept invalid TLS certificates, e.g. self-signed certificates of a custom --server-url.
+    /// This makes the connection vulnerable to man-in-the-middle attacks
+    #[arg(long, visible_alias = "danger-accept-invalid-certs")]
+    pub insecure: bool,
+
+    /// Send all requests through the given proxy [http://, https:// or socks5:// URL, credentials
+    /// can be passed as user:pass@host]. Defaults to the HTTP_PROXY and HTTPS_PROXY env vars
+    #[arg(long, value_parser = parse_proxy_url)]
+    pub proxy: Option<String>,
+
+    /// Base URL of the server to test against instead of speed.cloudflare.com. The server needs
+    /// to provide the same `/__down?bytes=` and `/__up` endpoints
+    #[arg(long, value_name = "URL", value_parser = parse_server_url, default_value = DEFAULT_BASE_URL)]
+    pub server_url: String,
+
+    /// Exit with status 2 if the best avg download throughput of all payload sizes is below the
+    /// given mbit/s
+    #[arg(long, value_name = "MBPS")]
+    pub min_download: Option<f64>,
+
+    /// Exit with status 2 if the best avg upload throughput of all payload sizes is below the
+    /// given mbit/s
+    #[arg(long, value_name = "MBPS")]
+    pub min_upload: Option<f64>,
+
+    /// Exit with status 2 if the avg latency is above the given ms
+    #[arg(long, value_name = "MS")]
+    pub max_latency: Option<f64>,
+
+    /// Compare the results against a json result saved with `--output-format json`, runs
+    /// appended with --append are compared against the last one
+    #[arg(long, value_name = "FILE")]
+    pub baseline: Option<PathBuf>,
+
+    /// Exit with status 2 if the download or upload throughput dropped or the latency rose by
+    /// more than the given percent compared to the --baseline
+    #[arg(long, value_name = "PERCENT", requires = "baseline")]
+    pub max_regression: Option<f64>,
+
+    /// Continuously repeat the speed test every given number of seconds until interrupted.
+    /// json output is written as one line per run, csv output writes the header only once
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub watch: Option<u64>,
+
+    /// Write the csv, json, influx or prometheus output to the given file instead of stdout.
+    /// prometheus output is written atomically for the node_exporter textfile collector
+    #[arg(long)]
+    pub output_file: Option<PathBuf>,
+
+    /// Append to the --output-file instead of overwriting it. The csv header is only written if
+    /// the file is new or empty, json output is appended as one line per run
+    #[arg(long, requires = "output_file")]
+    pub append: bool,
+}
+
+impl Default for SpeedTestCLIOptions {
+    fn default() -> Self {
+        Self::parse_from(["cfspeedtest"])
+    }
 }
 
 impl SpeedTestCLIOptions {
     /// Returns whether download tests should be performed
     pub fn should_download(&self) -> bool {
-        self.download_only || !self.upload_only
+        !self.latency_only && (self.download_only || !self.upload_only)
     }
 
     /// Returns whether upload tests should be performed
     pub fn should_upload(&self) -> bool {
-        self.upload_only || !self.download_only
+        !self.latency_only && (self.upload_only || !self.download_only)
+    }
+
+    /// Returns whether progress and informational messages are printed while the speed test is
+    /// running, i.e. for `OutputFormat::StdOut` without `--quiet`
+    pub fn shows_progress(&self) -> bool {
+        self.output_format == OutputFormat::StdOut && !self.quiet
+    }
+
+    /// Returns whether json output is written as one line per run, i.e. with `--append` or
+    /// `--watch`, even for `OutputFormat::JsonPretty`
+    pub fn writes_json_lines(&self) -> bool {
+        self.append || self.watch.is_some()
+    }
+
+    /// Returns the address family forced by `--ipv4` or `--ipv6`
+    pub fn ip_family(&self) -> Option<IpFamily> {
+        if self.ipv4 {
+            Some(IpFamily::Ipv4)
+        } else if self.ipv6 {
+            Some(IpFamily::Ipv6)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a copy of the options forced to the given address family, used for the runs of
+    /// `--dual-stack`
+    pub fn with_ip_family(&self, ip_family: IpFamily) -> Self {
+        Self {
+            ipv4: ip_family == IpFamily::Ipv4,
+            ipv6: ip_family == IpFamily::Ipv6,
+            dual_stack: false,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the payload sizes in bytes to run the download and upload tests with
+    pub fn payload_sizes(&self) -> Vec<usize> {
+        match &self.payload_sizes {
+            Some(payload_sizes) => payload_sizes.iter().map(|size| size.0).collect(),
+            None => PayloadSize::sizes_from_max(self.max_payload_size.clone()),
+        }
+    }
+
+    /// Validates combinations of options that can't be expressed with clap attributes
+    pub fn validate(&self) -> Result<(), String> {
+        if self.output_file.is_some()
+            && matches!(
+                self.output_format,
+                OutputFormat::StdOut | OutputFormat::None
+            )
+        {
+            return Err(
+                "--output-file requires --output-format to be one of csv, json, json-pretty, influx, prometheus or summary"
+                    .to_string(),
+            );
+        }
+        // NaN fails every comparison, so it's rejected by the `is_finite` check
+        if self
+            .max_mbps
+            .is_some_and(|max_mbps| !max_mbps.is_finite() || max_mbps <= 0.0)
+        {
+            return Err("--max-mbps needs to be greater than 0".to_string());
+        }
+        if !self.max_variation.is_finite() || self.max_variation < 0.0 {
+            return Err("--max-variation needs to be a non-negative number".to_string());
+        }
+        if !self.adaptive_tolerance.is_finite() || self.adaptive_tolerance < 0.0 {
+            return Err("--adaptive-tolerance needs to be a non-negative number".to_string());
+        }
+        if self.read_chunk_bytes == 0 || self.read_chunk_bytes > MAX_READ_CHUNK_BYTES {
+            return Err(format!(
+                "--read-chunk-bytes needs to be between 1 and {MAX_READ_CHUNK_BYTES}"
+            ));
+        }
+        if self.dual_stack && self.watch.is_some() {
+            return Err("--dual-stack can't be used with --watch".to_string());
+        }
+        if self.dual_stack && self.baseline.is_some() {
+            return Err("--dual-stack can't be used with --baseline".to_string());
+        }
+        if self.dual_stack && self.output_format == OutputFormat::Prometheus {
+            return Err(
+                "--dual-stack can't be used with prometheus output, use json, csv or influx instead"
+                    .to_string(),
+            );
+        }
+        if self.append && self.output_format == OutputFormat::Prometheus {
+            return Err(
+                "--append can't be used with prometheus output, the textfile only contains the latest run"
+                    .to_string(),
+            );
+        }
+        Ok(())
     }
 }
 
@@ -102,6 +527,40 @@ fn parse_payload_size(input_string: &str) -> Result<PayloadSize, String> {
     PayloadSize::from(input_string.to_string())
 }
 
+fn parse_read_chunk_bytes(input_string: &str) -> Result<usize, String> {
+    PayloadSize::from(input_string.to_string()).map(|size| size.0)
+}
+
+fn parse_proxy_url(input_string: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(input_string)
+        .map_err(|e| format!("Invalid proxy URL '{input_string}': {e}"))?;
+    match url.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => Ok(input_string.to_string()),
+        scheme => Err(format!(
+            "Unsupported proxy scheme '{scheme}', needs to be one of http, https or socks5"
+        )),
+    }
+}
+
+fn parse_server_url(input_string: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(input_string)
+        .map_err(|e| format!("Invalid server URL '{input_string}': {e}"))?;
+    match url.scheme() {
+        "http" | "https" => Ok(input_string.trim_end_matches('/').to_string()),
+        scheme => Err(format!(
+            "Unsupported server URL scheme '{scheme}', needs to be one of http or https"
+        )),
+    }
+}
+
+fn parse_http_version(input_string: &str) -> Result<HttpVersion, String> {
+    HttpVersion::from(input_string.to_string())
+}
+
+fn parse_unit(input_string: &str) -> Result<Unit, String> {
+    Unit::from(input_string.to_string())
+}
+
 fn parse_output_format(input_string: &str) -> Result<OutputFormat, String> {
     OutputFormat::from(input_string.to_string())
 }
@@ -1,31 +1,322 @@
-use cfspeedtest::speedtest;
+use cfspeedtest::baseline::{self, Baseline};
+use cfspeedtest::doh;
+use cfspeedtest::error::SpeedTestError;
+use cfspeedtest::interface::{interface_address, local_bind_address};
+use cfspeedtest::measurements::append_output_file;
+use cfspeedtest::measurements::create_output_file;
+use cfspeedtest::measurements::print_result;
+use cfspeedtest::measurements::write_dual_stack_result;
+use cfspeedtest::measurements::write_result;
+use cfspeedtest::measurements::write_result_to_file;
+use cfspeedtest::speedtest::{speed_test_with_cancellation, CancellationToken, SpeedTestResult};
+use cfspeedtest::thresholds::{
+    check_regressions, check_thresholds, ThresholdViolation, Thresholds,
+};
 use cfspeedtest::OutputFormat;
 use cfspeedtest::SpeedTestCLIOptions;
-use clap::Parser;
-use std::net::IpAddr;
-use std::time::Duration;
+use cfspeedtest::{HttpVersion, IpFamily};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::Url;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use speedtest::speed_test;
+/// Timeout of the DoH lookup if no --timeout-secs is given
+const DOH_TIMEOUT: Duration = Duration::from_secs(10);
 
 fn main() {
     env_logger::init();
     let options = SpeedTestCLIOptions::parse();
-    if options.output_format == OutputFormat::StdOut {
+    if let Err(message) = options.validate() {
+        SpeedTestCLIOptions::command()
+            .error(ErrorKind::ArgumentConflict, message)
+            .exit();
+    }
+    let cancellation = CancellationToken::new();
+    let interrupted = cancellation.clone();
+    ctrlc::set_handler(move || {
+        if interrupted.is_cancelled() {
+            // second interrupt, stop immediately
+            std::process::exit(130);
+        }
+        eprintln!("\nInterrupted, finishing the current measurement...");
+        interrupted.cancel();
+    })
+    .expect("Failed to set Ctrl-C handler");
+    if !options.quiet
+        && (options.output_format == OutputFormat::StdOut || options.output_file.is_some())
+    {
         println!("Starting Cloudflare speed test");
     }
 
-    let mut client_builder = reqwest::blocking::Client::builder();
-    if options.ipv4 {
-        client_builder = client_builder.local_address("0.0.0.0".parse::<IpAddr>().unwrap());
-    } else if options.ipv6 {
-        client_builder = client_builder.local_address("::1".parse::<IpAddr>().unwrap());
+    let outcome = match options.watch {
+        Some(interval_secs) => {
+            watch(options, Duration::from_secs(interval_secs), &cancellation).map(|_| vec![])
+        }
+        None if options.dual_stack => run_dual_stack(options, &cancellation),
+        None => run(options, &cancellation),
+    };
+    match outcome {
+        Ok(violations) if !violations.is_empty() => {
+            for violation in violations {
+                eprintln!("Threshold breached: {violation}");
+            }
+            std::process::exit(2);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn build_client(options: &SpeedTestCLIOptions) -> Result<Client, SpeedTestError> {
+    let mut client_builder = reqwest::blocking::Client::builder().user_agent(&options.user_agent);
+    let local_address = match &options.interface {
+        Some(name) => Some(interface_address(name, options.ipv4, options.ipv6)?),
+        None => local_bind_address(options),
+    };
+    if let Some(address) = local_address {
+        client_builder = client_builder.local_address(address);
+    }
+    if options.doh {
+        client_builder = pin_doh_address(client_builder, options, local_address);
+    }
+    if let Some(proxy_url) = &options.proxy {
+        let proxy = reqwest::Proxy::all(proxy_url).map_err(SpeedTestError::ClientBuild)?;
+        client_builder = client_builder.proxy(proxy);
+    }
+    match options.http_version {
+        Some(HttpVersion::Http1) => client_builder = client_builder.http1_only(),
+        Some(HttpVersion::Http2) => client_builder = client_builder.http2_prior_knowledge(),
+        None => {}
+    }
+    if options.insecure {
+        eprintln!(
+            "WARNING: TLS certificate verification is disabled, \
+             do not use --insecure against untrusted servers"
+        );
+        client_builder = client_builder.danger_accept_invalid_certs(true);
     }
     if let Some(timeout_secs) = options.timeout_secs {
         client_builder = client_builder.timeout(Some(Duration::from_secs(timeout_secs)));
     }
+    client_builder.build().map_err(SpeedTestError::ClientBuild)
+}
+
+/// Resolves the host of the server URL via DoH and pins the first address in the client.
+/// The system resolver is used with a warning if the lookup fails
+fn pin_doh_address(
+    client_builder: ClientBuilder,
+    options: &SpeedTestCLIOptions,
+    local_address: Option<IpAddr>,
+) -> ClientBuilder {
+    let host = match Url::parse(&options.server_url)
+        .ok()
+        .and_then(|url| url.domain().map(str::to_string))
+    {
+        Some(host) => host,
+        // IP addresses don't need to be resolved
+        None => return client_builder,
+    };
+    let resolver_client = Client::builder()
+        .user_agent(&options.user_agent)
+        .local_address(local_address)
+        .timeout(
+            options
+                .timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(DOH_TIMEOUT),
+        )
+        .build();
+    let addresses = resolver_client
+        .map_err(SpeedTestError::ClientBuild)
+        .and_then(|client| {
+            doh::resolve(
+                &client,
+                doh::resolver_url(options.ipv6),
+                &host,
+                options.ipv4,
+                options.ipv6,
+            )
+        });
+    match addresses {
+        Ok(addresses) => {
+            log::info!("resolved {host} to {addresses:?} via DoH");
+            // the port is taken from the server URL
+            client_builder.resolve(&host, SocketAddr::new(addresses[0], 0))
+        }
+        Err(e) => {
+            eprintln!("WARNING: {e}, falling back to the system resolver for {host}");
+            client_builder
+        }
+    }
+}
+
+/// Runs a single speed test and returns the breached thresholds
+fn run(
+    options: SpeedTestCLIOptions,
+    cancellation: &CancellationToken,
+) -> Result<Vec<ThresholdViolation>, SpeedTestError> {
+    // fail early on an unreadable baseline instead of after running the speed test
+    let baseline = options
+        .baseline
+        .as_deref()
+        .map(baseline::load_baseline)
+        .transpose()?;
+    let client = build_client(&options)?;
+    let result = speed_test_with_cancellation(client, options.clone(), None, cancellation.clone())?;
+    match &options.output_file {
+        Some(path) => {
+            write_result_to_file(&result, &options, path)?;
+            if !options.quiet {
+                println!("Results written to {}", path.display());
+            }
+        }
+        None => print_result(&result, &options)?,
+    }
+    let mut violations = check_thresholds(&result, &Thresholds::from_options(&options));
+    if let Some(baseline) = &baseline {
+        violations.extend(compare_with_baseline(&result, baseline, &options));
+    }
+    Ok(violations)
+}
 
-    let client = client_builder
-        .build()
-        .expect("Failed to initialize reqwest client");
-    speed_test(client, options);
+/// Runs the speed test once over IPv4 and once over IPv6 and returns the breached thresholds of
+/// both runs. A failed run is reported and skipped, an error is only returned if both failed
+fn run_dual_stack(
+    options: SpeedTestCLIOptions,
+    cancellation: &CancellationToken,
+) -> Result<Vec<ThresholdViolation>, SpeedTestError> {
+    let mut results = Vec::new();
+    let mut last_error = None;
+    for ip_family in [IpFamily::Ipv4, IpFamily::Ipv6] {
+        if cancellation.is_cancelled() {
+            break;
+        }
+        let family_options = options.with_ip_family(ip_family);
+        if options.shows_progress() {
+            println!("\n===== {} =====", ip_family.name());
+        }
+        let outcome = build_client(&family_options).and_then(|client| {
+            speed_test_with_cancellation(client, family_options, None, cancellation.clone())
+        });
+        match outcome {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                eprintln!("{} speed test failed: {e}", ip_family.name());
+                last_error = Some(e);
+            }
+        }
+    }
+    if let (true, Some(e)) = (results.is_empty(), last_error) {
+        return Err(e);
+    }
+    match &options.output_file {
+        Some(path) => {
+            let (file, include_header) = if options.append {
+                append_output_file(path)?
+            } else {
+                (create_output_file(path)?, true)
+            };
+            write_dual_stack_result(file, &results, &options, include_header)?;
+            if !options.quiet {
+                println!("Results written to {}", path.display());
+            }
+        }
+        None => write_dual_stack_result(io::stdout(), &results, &options, true)?,
+    }
+    let thresholds = Thresholds::from_options(&options);
+    Ok(results
+        .iter()
+        .flat_map(|result| check_thresholds(result, &thresholds))
+        .collect())
+}
+
+/// Prints the comparison with the baseline and returns the regressions exceeding
+/// `--max-regression`
+fn compare_with_baseline(
+    result: &SpeedTestResult,
+    baseline: &Baseline,
+    options: &SpeedTestCLIOptions,
+) -> Vec<ThresholdViolation> {
+    let deltas = baseline::compare(result, baseline);
+    baseline::print_comparison(&deltas, baseline, options);
+    match options.max_regression {
+        Some(max_percent) => check_regressions(&deltas, max_percent),
+        None => vec![],
+    }
+}
+
+/// Repeats the speed test every `interval` until interrupted, reusing the same client.
+/// Failed runs are logged and breached thresholds printed to stderr, neither stops the loop.
+fn watch(
+    options: SpeedTestCLIOptions,
+    interval: Duration,
+    cancellation: &CancellationToken,
+) -> Result<(), SpeedTestError> {
+    let client = build_client(&options)?;
+    let mut include_header = true;
+    let mut output_file = match &options.output_file {
+        Some(path) if options.append => {
+            let (file, is_empty) = append_output_file(path)?;
+            include_header = is_empty;
+            Some(file)
+        }
+        Some(path) if options.output_format != OutputFormat::Prometheus => {
+            Some(create_output_file(path)?)
+        }
+        _ => None,
+    };
+    let baseline = options
+        .baseline
+        .as_deref()
+        .map(baseline::load_baseline)
+        .transpose()?;
+    let thresholds = Thresholds::from_options(&options);
+    while !cancellation.is_cancelled() {
+        let start = Instant::now();
+        if options.shows_progress() {
+            println!(
+                "\n===== {} =====",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+            );
+        }
+        match speed_test_with_cancellation(
+            client.clone(),
+            options.clone(),
+            None,
+            cancellation.clone(),
+        ) {
+            Ok(result) => {
+                match (&mut output_file, &options.output_file) {
+                    (Some(file), _) => write_result(file, &result, &options, include_header)?,
+                    // prometheus textfiles are rewritten atomically and only contain the latest run
+                    (None, Some(path)) => write_result_to_file(&result, &options, path)?,
+                    (None, None) => write_result(io::stdout(), &result, &options, include_header)?,
+                }
+                include_header = false;
+                let mut violations = check_thresholds(&result, &thresholds);
+                if let Some(baseline) = &baseline {
+                    violations.extend(compare_with_baseline(&result, baseline, &options));
+                }
+                for violation in violations {
+                    eprintln!("Threshold breached: {violation}");
+                }
+            }
+            Err(e) => log::error!("speed test failed: {e}"),
+        }
+        // sleep in small steps to react to Ctrl-C while waiting for the next run
+        while !cancellation.is_cancelled() && start.elapsed() < interval {
+            thread::sleep(Duration::from_millis(100).min(interval.saturating_sub(start.elapsed())));
+        }
+    }
+    if let Some(path) = options.output_file.as_ref().filter(|_| !options.quiet) {
+        println!("Results written to {}", path.display());
+    }
+    Ok(())
 }
@@ -1,27 +1,155 @@
 use crate::boxplot;
+use crate::histogram::{render_histogram, HISTOGRAM_BUCKETS};
+use crate::speedtest::SpeedTestResult;
 use crate::speedtest::TestType;
+use crate::stats::{is_unstable, percentile, Statistics};
+use crate::thresholds::best_avg_mbps;
+use crate::IpFamily;
 use crate::OutputFormat;
+use crate::SpeedTestCLIOptions;
+use crate::Unit;
+use chrono::{DateTime, Utc};
 use indexmap::IndexSet;
-use serde::Serialize;
-use std::{fmt::Display, io};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write as _},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
 
-#[derive(Serialize)]
-struct StatMeasurement {
+/// Version of the json output schema, increased on every incompatible change of its shape
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Top-level object of the json output
+#[derive(Serialize, Deserialize)]
+pub(crate) struct JsonResult {
+    pub(crate) schema_version: u32,
+    /// Version of cfspeedtest which produced the output
+    version: String,
+    /// Completion time of the last measurement
+    pub(crate) timestamp: DateTime<Utc>,
+    /// Public IP address of the client, `None` if the trace request failed
+    client_ip: Option<String>,
+    /// Address family the speed test was forced to
+    #[serde(default)]
+    ip_family: Option<IpFamily>,
+    server_location: Option<String>,
+    http_version: String,
+    /// Whether measurements were skipped because the max duration was exceeded
+    truncated: bool,
+    /// Whether the throughput of any payload size varied by more than `--max-variation`
+    #[serde(default)]
+    unstable: bool,
+    /// Throughput cap in mbit/s applied with `--max-mbps`
+    #[serde(default)]
+    max_mbps: Option<f64>,
+    pub(crate) latency: Option<StatMeasurement>,
+    pub(crate) download: Option<ThroughputStats>,
+    pub(crate) upload: Option<ThroughputStats>,
+}
+
+/// Throughput stats of all payload sizes of a test type
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ThroughputStats {
+    pub(crate) unit: Unit,
+    pub(crate) payload_sizes: Vec<StatMeasurement>,
+}
+
+impl JsonResult {
+    fn new(
+        result: &SpeedTestResult,
+        options: &SpeedTestCLIOptions,
+        stat_measurements: Vec<StatMeasurement>,
+    ) -> Self {
+        let timestamp = stat_measurements
+            .iter()
+            .map(|m| m.timestamp)
+            .max()
+            .unwrap_or(result.latency_timestamp);
+        let mut latency = None;
+        let mut downloads = Vec::new();
+        let mut uploads = Vec::new();
+        for stat_measurement in stat_measurements {
+            match stat_measurement.test_type {
+                TestType::Latency => latency = Some(stat_measurement),
+                TestType::Download => downloads.push(stat_measurement),
+                TestType::Upload => uploads.push(stat_measurement),
+            }
+        }
+        let throughput_stats = |payload_sizes: Vec<StatMeasurement>| {
+            (!payload_sizes.is_empty()).then_some(ThroughputStats {
+                unit: options.unit,
+                payload_sizes,
+            })
+        };
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp,
+            client_ip: result.client_ip.clone(),
+            ip_family: result.ip_family,
+            server_location: result.server_location.clone(),
+            http_version: result.metadata.http_version.clone(),
+            truncated: result.truncated,
+            unstable: has_unstable_throughput(result, options.max_variation),
+            max_mbps: options.max_mbps,
+            latency,
+            download: throughput_stats(downloads),
+            upload: throughput_stats(uploads),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct StatMeasurement {
     test_type: TestType,
     payload_size: usize,
     min: f64,
-    q1: f64,
+    /// Quartiles, `None` if there are less than 4 measurements
+    q1: Option<f64>,
     median: f64,
-    q3: f64,
+    q3: Option<f64>,
     max: f64,
-    avg: f64,
+    pub(crate) avg: f64,
+    /// Avg time to first byte of the downloads in ms
+    ttfb_ms: Option<f64>,
+    jitter_ms: Option<f64>,
+    latency_p50_ms: Option<f64>,
+    latency_p90_ms: Option<f64>,
+    latency_p99_ms: Option<f64>,
+    /// Share of lost requests in percent, only measured with `--measure-loss`
+    packet_loss_percent: Option<f64>,
+    /// Unit of the throughput stats, `None` for latency stats which are in ms
+    unit: Option<Unit>,
+    /// Throughput of every single run in `unit` or latency in ms, only included in json output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    runs: Option<Vec<f64>>,
+    /// Completion time of every single run, only included in json output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run_timestamps: Option<Vec<DateTime<Utc>>>,
+    server_location: Option<String>,
+    #[serde(default)]
+    ip_family: Option<IpFamily>,
+    http_version: String,
+    /// Completion time of the last measurement
+    timestamp: DateTime<Utc>,
+    /// Whether measurements were skipped because the max duration was exceeded
+    truncated: bool,
 }
 
 #[derive(Serialize)]
 pub struct Measurement {
     pub test_type: TestType,
     pub payload_size: usize,
-    pub mbit: f64,
+    /// Throughput in bytes/s
+    pub bytes_per_sec: f64,
+    /// Time from sending the request until the first byte of the body arrived in ms,
+    /// only measured for downloads
+    pub ttfb_ms: Option<f64>,
+    /// Time at which the measurement completed
+    pub timestamp: DateTime<Utc>,
 }
 
 impl Display for Measurement {
@@ -31,79 +159,494 @@ impl Display for Measurement {
             "{:?}: \t{}\t-> {}",
             self.test_type,
             format_bytes(self.payload_size),
-            self.mbit,
+            Unit::Mbps.format(self.bytes_per_sec),
         )
     }
 }
 
-pub(crate) fn log_measurements(
-    measurements: &[Measurement],
-    payload_sizes: Vec<usize>,
-    verbose: bool,
-    output_format: OutputFormat,
-) {
-    if output_format == OutputFormat::StdOut {
+/// Prints the summary statistics of the given speed test result in the requested output format
+pub fn print_result(result: &SpeedTestResult, options: &SpeedTestCLIOptions) -> io::Result<()> {
+    write_result(io::stdout(), result, options, true)
+}
+
+/// Writes the summary statistics of the given speed test result to the file at `path` in the
+/// requested output format, creating missing parent directories
+pub fn write_result_to_file(
+    result: &SpeedTestResult,
+    options: &SpeedTestCLIOptions,
+    path: &Path,
+) -> io::Result<()> {
+    if options.output_format == OutputFormat::Prometheus {
+        // write to a temp file and rename it afterwards,
+        // so the textfile collector never reads a partially written file
+        let mut tmp_file_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_file_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_file_name);
+        write_result(create_output_file(&tmp_path)?, result, options, true)?;
+        return fs::rename(tmp_path, path);
+    }
+    if options.append {
+        let (file, is_empty) = append_output_file(path)?;
+        return write_result(file, result, options, is_empty);
+    }
+    write_result(create_output_file(path)?, result, options, true)
+}
+
+/// Creates (or truncates) the output file at `path`, creating missing parent directories
+pub fn create_output_file(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    File::create(path)
+}
+
+/// Opens the output file at `path` for appending, creating it and missing parent directories if
+/// needed. Also returns whether the file is empty, i.e. whether a csv header needs to be written
+pub fn append_output_file(path: &Path) -> io::Result<(File, bool)> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_empty = file.metadata()?.len() == 0;
+    Ok((file, is_empty))
+}
+
+/// Writes the summary statistics of the given speed test result to `writer`.
+/// `include_header` controls whether the csv header row is written, this allows appending
+/// the results of multiple runs to the same writer.
+pub fn write_result(
+    mut writer: impl Write,
+    result: &SpeedTestResult,
+    options: &SpeedTestCLIOptions,
+    include_header: bool,
+) -> io::Result<()> {
+    let output_format = options.output_format;
+    let measurements = &result.measurements;
+    if let (OutputFormat::StdOut, true, Some(avg_latency)) =
+        (output_format, options.quiet, result.avg_latency)
+    {
+        // the latency is otherwise printed while running the latency test
+        println!("Avg latency {avg_latency:.2} ms");
+        if let Some(jitter) = result.jitter_ms {
+            println!("Jitter {jitter:.2} ms");
+        }
+        if let Some(packet_loss) = result.packet_loss {
+            println!("Packet loss {packet_loss}");
+        }
+    }
+    if output_format == OutputFormat::StdOut && !measurements.is_empty() {
         println!("\nSummary Statistics");
-        println!("Type     Payload |  min/max/avg in mbit/s");
+        println!(
+            "Type     Payload |  min/max/avg in {}",
+            options.unit.label()
+        );
     }
+    let stat_measurements = aggregate_measurements(result, options);
+    match output_format {
+        OutputFormat::Csv => {
+            let mut wtr = csv::WriterBuilder::new()
+                .has_headers(include_header)
+                .from_writer(writer);
+            for measurement in &stat_measurements {
+                wtr.serialize(measurement)?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::JsonPretty if !options.writes_json_lines() => {
+            let json_result = JsonResult::new(result, options, stat_measurements);
+            serde_json::to_writer_pretty(&mut writer, &json_result)?;
+            writeln!(writer)?;
+        }
+        // appended or repeated json is always written as one line per run
+        OutputFormat::Json | OutputFormat::JsonPretty => {
+            let json_result = JsonResult::new(result, options, stat_measurements);
+            serde_json::to_writer(&mut writer, &json_result)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::InfluxLineProtocol => {
+            write!(writer, "{}", format_influx_line_protocol(result))?;
+        }
+        OutputFormat::Prometheus => {
+            write!(writer, "{}", format_prometheus(result))?;
+        }
+        OutputFormat::StdOut => {
+            if result.truncated {
+                println!("\nMax duration exceeded, the remaining measurements were skipped");
+            }
+            if let Some(max_mbps) = options.max_mbps.filter(|_| !measurements.is_empty()) {
+                println!(
+                    "\nThroughput was capped at {max_mbps} mbit/s with --max-mbps, \
+                     the results don't reflect the capacity of the connection"
+                );
+            }
+        }
+        OutputFormat::Summary => {
+            writeln!(writer, "{}", format_summary(result, options))?;
+        }
+        OutputFormat::None => {}
+    }
+    match output_format {
+        // json output sets the `unstable` flag instead
+        OutputFormat::Json | OutputFormat::JsonPretty | OutputFormat::None => {}
+        _ if !has_unstable_throughput(result, options.max_variation) => {}
+        OutputFormat::StdOut => println!("\nWARNING: {HIGH_VARIANCE_WARNING}"),
+        // keep the machine readable output on stdout parseable
+        _ => eprintln!("WARNING: {HIGH_VARIANCE_WARNING}"),
+    }
+    Ok(())
+}
+
+const HIGH_VARIANCE_WARNING: &str =
+    "High variance detected; results may be unreliable, consider increasing --runs-per-size";
+
+/// Returns whether the standard deviation of the throughput of any test type and payload size
+/// exceeds `max_variation_percent` of its mean
+pub fn has_unstable_throughput(result: &SpeedTestResult, max_variation_percent: f64) -> bool {
+    let mut stats_per_size: HashMap<(TestType, usize), Statistics> = HashMap::new();
+    for measurement in &result.measurements {
+        stats_per_size
+            .entry((measurement.test_type, measurement.payload_size))
+            .or_default()
+            .add_throughput(measurement.bytes_per_sec);
+    }
+    stats_per_size
+        .values()
+        .any(|stats| is_unstable(stats, max_variation_percent / 100.0))
+}
+
+/// Aggregates the latency and the measurements per test type and payload size, the throughput
+/// rows are printed for `OutputFormat::StdOut`
+fn aggregate_measurements(
+    result: &SpeedTestResult,
+    options: &SpeedTestCLIOptions,
+) -> Vec<StatMeasurement> {
+    let payload_sizes: Vec<usize> = result
+        .measurements
+        .iter()
+        .map(|m| m.payload_size)
+        .collect::<IndexSet<usize>>()
+        .into_iter()
+        .collect();
     let mut stat_measurements: Vec<StatMeasurement> = Vec::new();
-    measurements
+    // the avg latency is already printed while running the latency test for StdOut
+    if let Some(latency_stats) = latency_stat_measurement(result, options) {
+        stat_measurements.push(latency_stats);
+    }
+    result
+        .measurements
         .iter()
         .map(|m| m.test_type)
         .collect::<IndexSet<TestType>>()
         .iter()
         .for_each(|t| {
             stat_measurements.extend(log_measurements_by_test_type(
-                measurements,
+                result,
                 payload_sizes.clone(),
-                verbose,
-                output_format,
+                options,
                 *t,
             ))
         });
-    match output_format {
-        OutputFormat::Csv => {
-            let mut wtr = csv::Writer::from_writer(io::stdout());
-            for measurement in &stat_measurements {
-                wtr.serialize(measurement).unwrap();
+    stat_measurements
+}
+
+/// Results of a `--dual-stack` run nested under the address family in json output
+#[derive(Serialize)]
+struct DualStackJsonResult {
+    schema_version: u32,
+    ipv4: Option<JsonResult>,
+    ipv6: Option<JsonResult>,
+}
+
+/// Writes the results of a `--dual-stack` run to `writer`. json output nests the results under
+/// `ipv4` and `ipv6` keys, all other output formats write the results one after another,
+/// labeled with their address family
+pub fn write_dual_stack_result(
+    mut writer: impl Write,
+    results: &[SpeedTestResult],
+    options: &SpeedTestCLIOptions,
+    include_header: bool,
+) -> io::Result<()> {
+    if !matches!(
+        options.output_format,
+        OutputFormat::Json | OutputFormat::JsonPretty
+    ) {
+        for (i, result) in results.iter().enumerate() {
+            if options.output_format == OutputFormat::StdOut {
+                let family = result.ip_family.map_or("N/A", |family| family.name());
+                println!("\n===== {family} =====");
             }
-            wtr.flush().unwrap();
+            write_result(&mut writer, result, options, include_header && i == 0)?;
         }
-        OutputFormat::Json => {
-            serde_json::to_writer(io::stdout(), &stat_measurements).unwrap();
-            println!();
+        return Ok(());
+    }
+    let json_result = |ip_family: IpFamily| {
+        results
+            .iter()
+            .find(|result| result.ip_family == Some(ip_family))
+            .map(|result| JsonResult::new(result, options, aggregate_measurements(result, options)))
+    };
+    let dual_stack_result = DualStackJsonResult {
+        schema_version: JSON_SCHEMA_VERSION,
+        ipv4: json_result(IpFamily::Ipv4),
+        ipv6: json_result(IpFamily::Ipv6),
+    };
+    if options.output_format == OutputFormat::JsonPretty && !options.writes_json_lines() {
+        serde_json::to_writer_pretty(&mut writer, &dual_stack_result)?;
+    } else {
+        serde_json::to_writer(&mut writer, &dual_stack_result)?;
+    }
+    writeln!(writer)
+}
+
+/// Formats the result as a single line with fixed keys for shell scripts, e.g.
+/// `down=210.4 up=38.1 latency=12.3 jitter=1.2`. The throughput is the best avg throughput of
+/// all payload sizes in `options.unit`, latency and jitter are in ms. Missing values are `NA`.
+/// Results of a forced address family end with e.g. `family=ipv6`
+pub fn format_summary(result: &SpeedTestResult, options: &SpeedTestCLIOptions) -> String {
+    let format_value =
+        |value: Option<f64>| value.map_or("NA".to_string(), |value| format!("{value:.1}"));
+    let throughput = |test_type: TestType| {
+        best_avg_mbps(result, test_type)
+            .map(|mbps| options.unit.convert(Unit::Mbps.to_bytes_per_sec(mbps)))
+    };
+    let latency = result.avg_latency;
+    let family = result
+        .ip_family
+        .map(|ip_family| format!(" family={ip_family}"))
+        .unwrap_or_default();
+    format!(
+        "down={} up={} latency={} jitter={}{family}",
+        format_value(throughput(TestType::Download)),
+        format_value(throughput(TestType::Upload)),
+        format_value(latency),
+        format_value(result.jitter_ms)
+    )
+}
+
+/// Formats every measurement as a record in the InfluxDB line protocol, e.g.
+/// `cfspeedtest,type=download,size=10MB mbps=123.4 1700000000000000000`.
+/// The avg latency, jitter and packet loss are written as `cfspeedtest_latency`,
+/// `cfspeedtest_jitter` and `cfspeedtest_packet_loss`. Results of a forced address family are
+/// tagged with e.g. `family=ipv6`
+pub(crate) fn format_influx_line_protocol(result: &SpeedTestResult) -> String {
+    let mut lines = String::new();
+    let latency_timestamp = timestamp_nanos(result.latency_timestamp);
+    let family_tag = result
+        .ip_family
+        .map(|ip_family| format!(",family={ip_family}"))
+        .unwrap_or_default();
+    if let Some(avg_latency) = result.avg_latency {
+        writeln!(
+            lines,
+            "cfspeedtest_latency{family_tag} ms={avg_latency} {latency_timestamp}"
+        )
+        .unwrap();
+    }
+    if let Some(jitter_ms) = result.jitter_ms {
+        writeln!(
+            lines,
+            "cfspeedtest_jitter{family_tag} ms={jitter_ms} {latency_timestamp}"
+        )
+        .unwrap();
+    }
+    if let Some(percent) = result.packet_loss.and_then(|loss| loss.percent()) {
+        writeln!(
+            lines,
+            "cfspeedtest_packet_loss{family_tag} percent={percent} {latency_timestamp}"
+        )
+        .unwrap();
+    }
+    for measurement in &result.measurements {
+        writeln!(
+            lines,
+            "cfspeedtest,type={},size={}{family_tag} mbps={} {}",
+            format!("{:?}", measurement.test_type).to_lowercase(),
+            escape_influx_tag(&format_bytes(measurement.payload_size)),
+            Unit::Mbps.convert(measurement.bytes_per_sec),
+            timestamp_nanos(measurement.timestamp)
+        )
+        .unwrap();
+    }
+    lines
+}
+
+/// Formats the avg latency, jitter, packet loss and avg throughput per payload size as
+/// prometheus metrics in the text exposition format
+pub(crate) fn format_prometheus(result: &SpeedTestResult) -> String {
+    let mut lines = String::new();
+    if let Some(avg_latency) = result.avg_latency {
+        writeln!(
+            lines,
+            "# HELP cfspeedtest_latency_ms Avg GET request latency in ms\n\
+             # TYPE cfspeedtest_latency_ms gauge\n\
+             cfspeedtest_latency_ms {avg_latency}"
+        )
+        .unwrap();
+    }
+    if let Some(jitter_ms) = result.jitter_ms {
+        writeln!(
+            lines,
+            "# HELP cfspeedtest_jitter_ms Latency jitter in ms\n\
+             # TYPE cfspeedtest_jitter_ms gauge\n\
+             cfspeedtest_jitter_ms {jitter_ms}"
+        )
+        .unwrap();
+    }
+    if let Some(percent) = result.packet_loss.and_then(|loss| loss.percent()) {
+        writeln!(
+            lines,
+            "# HELP cfspeedtest_packet_loss_percent Share of lost requests in percent\n\
+             # TYPE cfspeedtest_packet_loss_percent gauge\n\
+             cfspeedtest_packet_loss_percent {percent}"
+        )
+        .unwrap();
+    }
+    for (test_type, name) in [
+        (TestType::Download, "download"),
+        (TestType::Upload, "upload"),
+    ] {
+        let type_measurements: Vec<&Measurement> = result
+            .measurements
+            .iter()
+            .filter(|m| m.test_type == test_type)
+            .collect();
+        if type_measurements.is_empty() {
+            continue;
         }
-        OutputFormat::JsonPretty => {
-            // json_pretty output test
-            serde_json::to_writer_pretty(io::stdout(), &stat_measurements).unwrap();
-            println!();
+        let metric = format!("cfspeedtest_{name}_mbps");
+        writeln!(
+            lines,
+            "# HELP {metric} Avg {name} throughput in mbit/s per payload size\n\
+             # TYPE {metric} gauge"
+        )
+        .unwrap();
+        let payload_sizes: IndexSet<usize> =
+            type_measurements.iter().map(|m| m.payload_size).collect();
+        for payload_size in payload_sizes {
+            let stats = Statistics::new();
+            type_measurements
+                .iter()
+                .filter(|m| m.payload_size == payload_size)
+                .for_each(|m| stats.add_throughput(m.bytes_per_sec));
+            let Some(avg) = stats.mean_mbps() else {
+                continue;
+            };
+            writeln!(
+                lines,
+                "{metric}{{payload_size=\"{}\"}} {avg}",
+                format_bytes(payload_size)
+            )
+            .unwrap();
         }
-        OutputFormat::StdOut => {}
-        OutputFormat::None => {}
     }
+    lines
+}
+
+fn escape_influx_tag(value: &str) -> String {
+    value
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn timestamp_nanos(timestamp: DateTime<Utc>) -> i64 {
+    timestamp.timestamp_nanos_opt().unwrap_or_default()
+}
+
+/// Stats of the latency measurements, `None` if no latency was measured
+fn latency_stat_measurement(
+    result: &SpeedTestResult,
+    options: &SpeedTestCLIOptions,
+) -> Option<StatMeasurement> {
+    let latencies = result.latencies();
+    let Stats {
+        min,
+        q1,
+        median,
+        q3,
+        max,
+        avg,
+    } = calc_stats(latencies.clone())?;
+    let timestamps: Vec<DateTime<Utc>> = result
+        .latency_measurements
+        .iter()
+        .map(|(_, timestamp)| *timestamp)
+        .collect();
+    let is_json = is_json(options.output_format);
+    Some(StatMeasurement {
+        test_type: TestType::Latency,
+        payload_size: 0,
+        min,
+        q1,
+        median,
+        q3,
+        max,
+        avg,
+        ttfb_ms: None,
+        jitter_ms: result.jitter_ms,
+        latency_p50_ms: percentile(&latencies, 50.0),
+        latency_p90_ms: percentile(&latencies, 90.0),
+        latency_p99_ms: percentile(&latencies, 99.0),
+        packet_loss_percent: result.packet_loss.and_then(|loss| loss.percent()),
+        unit: None,
+        timestamp: timestamps
+            .iter()
+            .max()
+            .copied()
+            .unwrap_or(result.latency_timestamp),
+        runs: is_json.then_some(latencies),
+        run_timestamps: is_json.then_some(timestamps),
+        server_location: result.server_location.clone(),
+        ip_family: result.ip_family,
+        http_version: result.metadata.http_version.clone(),
+        truncated: result.truncated,
+    })
 }
 
 fn log_measurements_by_test_type(
-    measurements: &[Measurement],
+    result: &SpeedTestResult,
     payload_sizes: Vec<usize>,
-    verbose: bool,
-    output_format: OutputFormat,
+    options: &SpeedTestCLIOptions,
     test_type: TestType,
 ) -> Vec<StatMeasurement> {
     let mut stat_measurements: Vec<StatMeasurement> = Vec::new();
     for payload_size in payload_sizes {
-        let type_measurements: Vec<f64> = measurements
+        let type_measurements: Vec<&Measurement> = result
+            .measurements
             .iter()
             .filter(|m| m.test_type == test_type)
             .filter(|m| m.payload_size == payload_size)
-            .map(|m| m.mbit)
+            .collect();
+        let timestamp = type_measurements.iter().map(|m| m.timestamp).max();
+        let throughputs: Vec<f64> = type_measurements
+            .iter()
+            .map(|m| options.unit.convert(m.bytes_per_sec))
             .collect();
 
-        // check if there are any measurements for the current payload_size
-        // skip stats calculation if there are no measurements
-        if !type_measurements.is_empty() {
-            let (min, q1, median, q3, max, avg) = calc_stats(type_measurements).unwrap();
+        let ttfb_ms =
+            Statistics::from_samples(type_measurements.iter().filter_map(|m| m.ttfb_ms).collect())
+                .mean();
+        let is_json = is_json(options.output_format);
+        let runs = is_json.then(|| throughputs.clone());
+        let run_timestamps =
+            is_json.then(|| type_measurements.iter().map(|m| m.timestamp).collect());
+        let histogram = (options.histogram && throughputs.len() > 1)
+            .then(|| render_histogram(&throughputs, HISTOGRAM_BUCKETS));
 
+        // payload sizes cut short, e.g. because the speed test was cancelled, are reported with
+        // the stats their measurements allow
+        if let (
+            Some(Stats {
+                min,
+                q1,
+                median,
+                q3,
+                max,
+                avg,
+            }),
+            Some(timestamp),
+        ) = (calc_stats(throughputs), timestamp)
+        {
             let formatted_payload = format_bytes(payload_size);
             let fmt_test_type = format!("{:?}", test_type);
             stat_measurements.push(StatMeasurement {
@@ -115,12 +658,29 @@ fn log_measurements_by_test_type(
                 q3,
                 max,
                 avg,
+                ttfb_ms,
+                jitter_ms: None,
+                latency_p50_ms: None,
+                latency_p90_ms: None,
+                latency_p99_ms: None,
+                packet_loss_percent: None,
+                unit: Some(options.unit),
+                runs,
+                run_timestamps,
+                server_location: result.server_location.clone(),
+                ip_family: result.ip_family,
+                http_version: result.metadata.http_version.clone(),
+                timestamp,
+                truncated: result.truncated,
             });
-            if output_format == OutputFormat::StdOut {
+            if options.output_format == OutputFormat::StdOut {
                 println!(
                 "{fmt_test_type:<9} {formatted_payload:<7}|  min {min:<7.2} max {max:<7.2} avg {avg:<7.2}"
             );
-                if verbose {
+                if let Some(histogram) = &histogram {
+                    println!("{:<17}|  {histogram}", "");
+                }
+                if let (true, Some(q1), Some(q3)) = (options.verbose, q1, q3) {
                     let plot = boxplot::render_plot(min, q1, median, q3, max);
                     println!("{plot}\n");
                 }
@@ -131,51 +691,58 @@ fn log_measurements_by_test_type(
     stat_measurements
 }
 
-fn calc_stats(mbit_measurements: Vec<f64>) -> Option<(f64, f64, f64, f64, f64, f64)> {
-    log::debug!("calc_stats for mbit_measurements {mbit_measurements:?}");
-    let length = mbit_measurements.len();
-    if length < 4 {
-        return None;
-    }
-
-    let mut sorted_data = mbit_measurements.clone();
-    sorted_data.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Less));
-
-    let q1 = if length % 2 == 0 {
-        median(&sorted_data[0..length / 2])
-    } else {
-        median(&sorted_data[0..(length + 1) / 2])
-    };
+fn is_json(output_format: OutputFormat) -> bool {
+    matches!(output_format, OutputFormat::Json | OutputFormat::JsonPretty)
+}
 
-    let q3 = if length % 2 == 0 {
-        median(&sorted_data[length / 2..length])
-    } else {
-        median(&sorted_data[(length + 1) / 2..length])
-    };
+/// Summary stats of a set of measurements
+struct Stats {
+    min: f64,
+    /// `None` if there are less than 4 measurements
+    q1: Option<f64>,
+    median: f64,
+    /// `None` if there are less than 4 measurements
+    q3: Option<f64>,
+    max: f64,
+    avg: f64,
+}
 
-    Some((
-        *sorted_data.first().unwrap(),
+/// Returns `None` if there are no measurements
+fn calc_stats(measurements: Vec<f64>) -> Option<Stats> {
+    log::debug!("calc_stats for measurements {measurements:?}");
+    let stats = Statistics::from_samples(measurements);
+    let (q1, q3) = stats.quartiles().unzip();
+    Some(Stats {
+        min: stats.min()?,
         q1,
-        median(&sorted_data),
+        median: stats.median()?,
         q3,
-        *sorted_data.last().unwrap(),
-        mbit_measurements.iter().sum::<f64>() / mbit_measurements.len() as f64,
-    ))
+        max: stats.max()?,
+        avg: stats.mean()?,
+    })
 }
 
-fn median(data: &[f64]) -> f64 {
-    let length = data.len();
-    if length % 2 == 0 {
-        (data[length / 2 - 1] + data[length / 2]) / 2.0
-    } else {
-        data[length / 2]
+/// Calculates the jitter as the mean absolute difference between consecutive latency samples.
+/// Returns `None` if there are less than two samples
+pub fn calc_jitter(latency_measurements: &[f64]) -> Option<f64> {
+    if latency_measurements.len() < 2 {
+        return None;
     }
+    let total_diff: f64 = latency_measurements
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]).abs())
+        .sum();
+    Some(total_diff / (latency_measurements.len() - 1) as f64)
 }
 
+/// Formats a payload size in decimal units, e.g. "1MB" or "1.5MB". Fractions are kept so
+/// different sizes never share a label of the prometheus and influx output
 pub(crate) fn format_bytes(bytes: usize) -> String {
-    match bytes {
-        1_000..=999_999 => format!("{}KB", bytes / 1_000),
-        1_000_000..=999_999_999 => format!("{}MB", bytes / 1_000_000),
-        _ => format!("{bytes} bytes"),
-    }
+    let (divisor, unit) = match bytes {
+        1_000..=999_999 => (1_000, "KB"),
+        1_000_000..=999_999_999 => (1_000_000, "MB"),
+        1_000_000_000.. => (1_000_000_000, "GB"),
+        _ => return format!("{bytes} bytes"),
+    };
+    format!("{}{unit}", bytes as f64 / divisor as f64)
 }
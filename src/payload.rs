@@ -0,0 +1,39 @@
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::io::{self, Read};
+
+/// Max number of bytes yielded by a single read of `RandomPayload`
+pub const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Reader yielding `len` pseudo-random bytes used as upload payload. Random bytes can't be
+/// compressed by proxies on the path, which would inflate the measured upload throughput.
+/// The bytes are generated on the fly in chunks of at most `CHUNK_BYTES`, so memory usage is
+/// bounded regardless of the payload size
+pub struct RandomPayload {
+    rng: StdRng,
+    remaining: usize,
+}
+
+impl RandomPayload {
+    /// Creates a payload of `len` bytes. The same `seed` always yields the same bytes, the
+    /// generator is seeded from OS entropy without a seed
+    pub fn new(len: usize, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            rng,
+            remaining: len,
+        }
+    }
+}
+
+impl Read for RandomPayload {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.remaining.min(buf.len()).min(CHUNK_BYTES);
+        self.rng.fill_bytes(&mut buf[..len]);
+        self.remaining -= len;
+        Ok(len)
+    }
+}
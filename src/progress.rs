@@ -1,15 +1,93 @@
-use std::io::stdout;
-use std::io::Write;
-
-pub fn print_progress(name: &str, curr: u32, max: u32) {
-    const BAR_LEN: u32 = 30;
-    let progress_line = ((curr as f32 / max as f32) * BAR_LEN as f32) as u32;
-    let remaining_line = BAR_LEN - progress_line;
-    print!(
-        "\r{:<15} [{}{}]",
-        name,
-        (0..progress_line).map(|_| "=").collect::<String>(),
-        (0..remaining_line).map(|_| "-").collect::<String>(),
+use crate::speedtest::TestType;
+use crate::OutputFormat;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, IsTerminal, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Progress of a single download or upload measurement
+#[derive(Clone, Copy, Debug)]
+pub struct ProgressEvent {
+    pub test_type: TestType,
+    pub payload_size: usize,
+    /// Bytes transferred so far, summed up over all parallel connections
+    pub bytes_transferred: usize,
+    /// Time elapsed since the measurement started
+    pub elapsed: Duration,
+}
+
+/// Callback invoked with a `ProgressEvent` whenever a chunk was transferred during a download
+/// or upload measurement. It needs to be `Send + Sync` as it's called from every connection
+/// with `--parallel`
+pub type ProgressCallback = Box<dyn Fn(ProgressEvent) + Send + Sync>;
+
+/// Shared `ProgressCallback`, cloned into the upload body of every measurement
+pub(crate) type ProgressHandler = Arc<dyn Fn(ProgressEvent) + Send + Sync>;
+
+/// Called with the number of bytes transferred since the previous call
+pub(crate) type OnProgress = Arc<dyn Fn(usize) + Send + Sync>;
+
+/// Returns an `OnProgress` callback for a single measurement which sums up the transferred
+/// bytes and passes them on as `ProgressEvent` to `handler`
+pub(crate) fn report_progress(
+    test_type: TestType,
+    payload_size: usize,
+    handler: impl Fn(ProgressEvent) + Send + Sync + 'static,
+) -> OnProgress {
+    let transferred = AtomicUsize::new(0);
+    let start = Instant::now();
+    Arc::new(move |bytes| {
+        let bytes_transferred = transferred.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        handler(ProgressEvent {
+            test_type,
+            payload_size,
+            bytes_transferred,
+            elapsed: start.elapsed(),
+        });
+    })
+}
+
+/// Returns whether progress should be rendered, i.e. the output format is `OutputFormat::StdOut`
+/// and stdout is a terminal
+pub fn show_progress(output_format: OutputFormat) -> bool {
+    output_format == OutputFormat::StdOut && io::stdout().is_terminal()
+}
+
+/// Creates a progress bar with `len` steps, a hidden progress bar ignoring all updates is
+/// returned if `visible` is false
+pub(crate) fn progress_bar(name: &str, len: u64, visible: bool) -> ProgressBar {
+    if !visible {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::stdout());
+    bar.set_style(
+        ProgressStyle::with_template("{prefix:<15} [{bar:30}] {msg}")
+            .expect("invalid progress bar template")
+            .progress_chars("=-"),
     );
-    stdout().flush().expect("error printing progress bar");
+    bar.set_prefix(name.to_string());
+    bar
+}
+
+/// Reader reporting the number of bytes read from `inner` to `on_progress`
+pub(crate) struct ProgressReader<R> {
+    inner: R,
+    on_progress: OnProgress,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub(crate) fn new(inner: R, on_progress: OnProgress) -> Self {
+        Self { inner, on_progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.inner.read(buf)?;
+        if bytes > 0 {
+            (self.on_progress)(bytes);
+        }
+        Ok(bytes)
+    }
 }
@@ -0,0 +1,62 @@
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Limits the throughput of a single transfer by sleeping whenever it got ahead of the max rate
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    start: Instant,
+    transferred: usize,
+}
+
+impl RateLimiter {
+    /// Starts limiting a transfer to `bytes_per_sec` from now
+    pub fn new(bytes_per_sec: f64) -> Self {
+        Self {
+            bytes_per_sec,
+            start: Instant::now(),
+            transferred: 0,
+        }
+    }
+
+    /// Returns how long to wait after `transferred` bytes were transferred in `elapsed` to stay
+    /// under `bytes_per_sec`, zero if the transfer is behind the max rate
+    pub fn delay(bytes_per_sec: f64, transferred: usize, elapsed: Duration) -> Duration {
+        if bytes_per_sec <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(transferred as f64 / bytes_per_sec).saturating_sub(elapsed)
+    }
+
+    /// Records `bytes` transferred and sleeps as long as needed to stay under the max rate
+    pub fn throttle(&mut self, bytes: usize) {
+        self.transferred += bytes;
+        let delay = Self::delay(self.bytes_per_sec, self.transferred, self.start.elapsed());
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Reader throttling the reads from `inner` with the given `RateLimiter`, reads are passed
+/// through unchanged without a limiter
+pub struct RateLimitedReader<R> {
+    inner: R,
+    limiter: Option<RateLimiter>,
+}
+
+impl<R: Read> RateLimitedReader<R> {
+    pub fn new(inner: R, limiter: Option<RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<R: Read> Read for RateLimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.inner.read(buf)?;
+        if let Some(limiter) = &mut self.limiter {
+            limiter.throttle(bytes);
+        }
+        Ok(bytes)
+    }
+}
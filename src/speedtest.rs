@@ -1,303 +1,1090 @@
+use crate::error::SpeedTestError;
+use crate::measurements::calc_jitter;
 use crate::measurements::format_bytes;
-use crate::measurements::log_measurements;
 use crate::measurements::Measurement;
-use crate::progress::print_progress;
-use crate::OutputFormat;
+use crate::payload::RandomPayload;
+use crate::progress::{
+    progress_bar, report_progress, show_progress, OnProgress, ProgressCallback, ProgressHandler,
+    ProgressReader,
+};
+use crate::rate_limit::{RateLimitedReader, RateLimiter};
+use crate::stats::{has_converged, Statistics};
 use crate::SpeedTestCLIOptions;
+use crate::Unit;
+use crate::{IpFamily, OutputFormat};
+use chrono::{DateTime, Utc};
 use log;
 use regex::Regex;
-use reqwest::{blocking::Client, StatusCode};
-use serde::Serialize;
+use reqwest::blocking::{Body, Client};
+use reqwest::{StatusCode, Version};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fmt::Display,
+    io::Read,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
     time::{Duration, Instant},
 };
 
-const BASE_URL: &str = "https://speed.cloudflare.com";
-const DOWNLOAD_URL: &str = "__down?bytes=";
-const UPLOAD_URL: &str = "__up";
+pub(crate) const DEFAULT_BASE_URL: &str = "https://speed.cloudflare.com";
+pub(crate) const DOWNLOAD_URL: &str = "__down?bytes=";
+pub(crate) const UPLOAD_URL: &str = "__up";
+pub(crate) const TRACE_URL: &str = "cdn-cgi/trace";
+pub(crate) const LOCATIONS_URL: &str = "locations";
 
-#[derive(Clone, Copy, Debug, Hash, Serialize, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Hash, Serialize, Deserialize, Eq, PartialEq)]
 pub enum TestType {
     Download,
     Upload,
+    Latency,
 }
 
-#[derive(Clone, Debug)]
-pub enum PayloadSize {
-    K100 = 100_000,
-    M1 = 1_000_000,
-    M10 = 10_000_000,
-    M25 = 25_000_000,
-    M100 = 100_000_000,
-}
+/// Payload size in bytes used for download and upload tests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadSize(pub usize);
 
 impl Display for PayloadSize {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", format_bytes(self.clone() as usize))
+        write!(f, "{}", format_bytes(self.0))
     }
 }
 
 impl PayloadSize {
+    pub const K100: PayloadSize = PayloadSize(100_000);
+    pub const M1: PayloadSize = PayloadSize(1_000_000);
+    pub const M10: PayloadSize = PayloadSize(10_000_000);
+    pub const M25: PayloadSize = PayloadSize(25_000_000);
+    pub const M100: PayloadSize = PayloadSize(100_000_000);
+
+    /// Parses a payload size like "100000", "100_000", "100k", "10MB" or "1.5gb".
+    /// Units are case-insensitive and decimal, i.e. 1KB = 1000 bytes
     pub fn from(payload_string: String) -> Result<Self, String> {
-        match payload_string.to_lowercase().as_str() {
-            "100_000" | "100000" | "100k" | "100kb" => Ok(Self::K100),
-            "1_000_000" | "1000000" | "1m" | "1mb" => Ok(Self::M1),
-            "10_000_000" | "10000000" | "10m" | "10mb" => Ok(Self::M10),
-            "25_000_000" | "25000000" | "25m" | "25mb" => Ok(Self::M25),
-            "100_000_000" | "100000000" | "100m" | "100mb" => Ok(Self::M100),
-            _ => Err("Value needs to be one of 100k, 1m, 10m, 25m or 100m".to_string()),
+        let normalized = payload_string.trim().to_lowercase().replace('_', "");
+        let unit_start = normalized
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(normalized.len());
+        let (number, unit) = normalized.split_at(unit_start);
+        let multiplier = match unit {
+            "" | "b" => 1.0,
+            "k" | "kb" => 1_000.0,
+            "m" | "mb" => 1_000_000.0,
+            "g" | "gb" => 1_000_000_000.0,
+            _ => {
+                return Err(format!(
+                    "Invalid unit in '{payload_string}', needs to be one of KB, MB or GB"
+                ))
+            }
+        };
+        let value: f64 = number
+            .parse()
+            .map_err(|_| format!("Invalid payload size '{payload_string}', expected e.g. 10MB"))?;
+        let bytes = (value * multiplier).round() as usize;
+        if bytes == 0 {
+            return Err(format!(
+                "Payload size '{payload_string}' needs to be greater than 0"
+            ));
         }
+        Ok(Self(bytes))
     }
 
+    /// Returns the default payload sizes up to and including `max_payload_size`
     pub fn sizes_from_max(max_payload_size: PayloadSize) -> Vec<usize> {
         log::debug!("getting payload iterations for max_payload_size {max_payload_size:?}");
-        let payload_bytes: Vec<usize> =
-            vec![100_000, 1_000_000, 10_000_000, 25_000_000, 100_000_000];
-        match max_payload_size {
-            PayloadSize::K100 => payload_bytes[0..1].to_vec(),
-            PayloadSize::M1 => payload_bytes[0..2].to_vec(),
-            PayloadSize::M10 => payload_bytes[0..3].to_vec(),
-            PayloadSize::M25 => payload_bytes[0..4].to_vec(),
-            PayloadSize::M100 => payload_bytes[0..5].to_vec(),
-        }
+        let mut payload_bytes: Vec<usize> =
+            [Self::K100, Self::M1, Self::M10, Self::M25, Self::M100]
+                .iter()
+                .map(|size| size.0)
+                .filter(|size| *size < max_payload_size.0)
+                .collect();
+        payload_bytes.push(max_payload_size.0);
+        payload_bytes
     }
 }
 
 pub struct Metadata {
-    city: String,
-    country: String,
-    ip: String,
-    asn: String,
-    colo: String,
+    pub city: String,
+    pub country: String,
+    pub ip: String,
+    pub asn: String,
+    pub colo: String,
+    /// HTTP version negotiated with the server, e.g. "HTTP/2.0"
+    pub http_version: String,
 }
 
 impl Display for Metadata {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "City: {}\nCountry: {}\nIp: {}\nAsn: {}\nColo: {}",
-            self.city, self.country, self.ip, self.asn, self.colo
+            "City: {}\nCountry: {}\nIp: {}\nAsn: {}\nColo: {}\nHTTP version: {}",
+            self.city, self.country, self.ip, self.asn, self.colo, self.http_version
         )
     }
 }
 
-pub fn speed_test(client: Client, options: SpeedTestCLIOptions) -> Vec<Measurement> {
-    let metadata = fetch_metadata(&client);
-    if options.output_format == OutputFormat::StdOut {
+/// Shared flag to stop a running speed test, clones refer to the same flag
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the speed test using this token to stop. The measurement currently in flight is
+    /// finished and the measurements completed so far are returned.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether `cancel` was called on this token or one of its clones
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Overall time budget of a speed test, see `--max-duration`. It also carries the
+/// `CancellationToken` of the speed test
+pub struct TimeBudget {
+    deadline: Option<Instant>,
+    exhausted: AtomicBool,
+    cancellation: CancellationToken,
+}
+
+impl TimeBudget {
+    /// Starts a budget of `max_duration` from now, `None` never runs out
+    pub fn start(max_duration: Option<Duration>, cancellation: CancellationToken) -> Self {
+        Self {
+            deadline: max_duration.map(|max_duration| Instant::now() + max_duration),
+            exhausted: AtomicBool::new(false),
+            cancellation,
+        }
+    }
+
+    /// Returns whether the speed test was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Returns whether the budget ran out, in which case no new measurement should be started
+    pub fn is_exhausted(&self) -> bool {
+        let exhausted = self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline);
+        if exhausted {
+            self.exhausted.store(true, Ordering::Relaxed);
+        }
+        exhausted
+    }
+
+    /// Returns whether a measurement was skipped because the budget ran out
+    pub fn was_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+}
+
+/// Aggregated results of a full speed test run
+pub struct SpeedTestResult {
+    pub metadata: Metadata,
+    /// City and colo code of the Cloudflare datacenter serving the test, e.g. "Frankfurt (FRA)"
+    pub server_location: Option<String>,
+    /// Public IP address of the client as seen by the server, e.g. the egress IP of a VPN
+    pub client_ip: Option<String>,
+    /// Address family the speed test was forced to, `None` if it wasn't forced
+    pub ip_family: Option<IpFamily>,
+    /// Latency samples in the order they were measured
+    pub latency_measurements: Vec<LatencySample>,
+    /// Avg latency in ms, `None` if no latency was measured, e.g. with `--no-latency`
+    pub avg_latency: Option<f64>,
+    /// Time at which the latency test completed
+    pub latency_timestamp: DateTime<Utc>,
+    /// Mean absolute difference between consecutive latency measurements in ms
+    pub jitter_ms: Option<f64>,
+    pub measurements: Vec<Measurement>,
+    /// Whether measurements were skipped because `--max-duration` was exceeded
+    pub truncated: bool,
+    /// Outcome of the packet loss test, `None` without `--measure-loss`
+    pub packet_loss: Option<PacketLoss>,
+}
+
+/// Latency in ms and completion time of a latency sample
+pub type LatencySample = (f64, DateTime<Utc>);
+
+impl SpeedTestResult {
+    /// Returns the latency samples in ms without their timestamps
+    pub fn latencies(&self) -> Vec<f64> {
+        latencies(&self.latency_measurements)
+    }
+}
+
+pub(crate) fn latencies(latency_measurements: &[LatencySample]) -> Vec<f64> {
+    latency_measurements
+        .iter()
+        .map(|(latency, _)| *latency)
+        .collect()
+}
+
+/// Outcome of the packet loss test, see `--measure-loss`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacketLoss {
+    /// Number of requests sent
+    pub attempted: u32,
+    /// Requests which exceeded the deadline
+    pub timed_out: u32,
+    /// Requests which failed without a response, e.g. because the connection was reset
+    pub failed: u32,
+}
+
+impl PacketLoss {
+    /// Number of requests without a response within the deadline
+    pub fn lost(&self) -> u32 {
+        self.timed_out + self.failed
+    }
+
+    /// Share of lost requests in percent of the attempted requests, `None` if no request was sent
+    pub fn percent(&self) -> Option<f64> {
+        (self.attempted > 0).then(|| self.lost() as f64 * 100.0 / self.attempted as f64)
+    }
+}
+
+impl Display for PacketLoss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2}% ({} of {} requests lost, {} timed out, {} failed)",
+            self.percent().unwrap_or_default(),
+            self.lost(),
+            self.attempted,
+            self.timed_out,
+            self.failed
+        )
+    }
+}
+
+/// Runs the latency, download and upload tests and returns the collected results.
+/// Progress is only printed for `OutputFormat::StdOut`, progress bars with the live throughput
+/// are only shown if stdout is a terminal. Use `measurements::print_result` to print the
+/// summary of the returned measurements.
+pub fn speed_test(
+    client: Client,
+    options: SpeedTestCLIOptions,
+) -> Result<SpeedTestResult, SpeedTestError> {
+    speed_test_with_progress(client, options, None)
+}
+
+/// Same as `speed_test`, but additionally calls `on_progress` for every chunk transferred during
+/// the download and upload measurements. It's never called after a measurement completed
+pub fn speed_test_with_progress(
+    client: Client,
+    options: SpeedTestCLIOptions,
+    on_progress: Option<ProgressCallback>,
+) -> Result<SpeedTestResult, SpeedTestError> {
+    speed_test_with_cancellation(client, options, on_progress, CancellationToken::new())
+}
+
+/// Same as `speed_test_with_progress`, but stops starting new measurements once `cancellation`
+/// was cancelled, e.g. from a Ctrl-C handler
+pub fn speed_test_with_cancellation(
+    client: Client,
+    options: SpeedTestCLIOptions,
+    on_progress: Option<ProgressCallback>,
+    cancellation: CancellationToken,
+) -> Result<SpeedTestResult, SpeedTestError> {
+    let on_progress: Option<ProgressHandler> = on_progress.map(Arc::from);
+    let budget = TimeBudget::start(
+        options.max_duration.map(Duration::from_secs),
+        cancellation.clone(),
+    );
+    let metadata = fetch_metadata(&client, &options.server_url)?;
+    let Trace {
+        server_location,
+        client_ip,
+    } = fetch_trace(&client, &options.server_url);
+    if options.shows_progress() {
         println!("{metadata}");
+        println!(
+            "Server location: {}",
+            server_location.as_deref().unwrap_or("N/A")
+        );
+        println!("Your IP: {}", client_ip.as_deref().unwrap_or("N/A"));
     }
-    run_latency_test(&client, options.nr_latency_tests, options.output_format);
-    let payload_sizes = PayloadSize::sizes_from_max(options.max_payload_size.clone());
+    let (latency_measurements, avg_latency) = if options.no_latency {
+        (Vec::new(), None)
+    } else {
+        latency_test(
+            &client,
+            &options.server_url,
+            options.nr_latency_tests,
+            if options.shows_progress() {
+                OutputFormat::StdOut
+            } else {
+                OutputFormat::None
+            },
+            &cancellation,
+        )?
+    };
+    let latency_timestamp = Utc::now();
+    let jitter_ms = calc_jitter(&latencies(&latency_measurements));
+    let packet_loss = options.measure_loss.then(|| {
+        run_packet_loss_test(
+            &client,
+            &options.server_url,
+            options.loss_requests,
+            Duration::from_millis(options.loss_timeout_ms),
+            if options.shows_progress() {
+                OutputFormat::StdOut
+            } else {
+                OutputFormat::None
+            },
+            &cancellation,
+        )
+    });
+    let payload_sizes = options.payload_sizes();
     let mut measurements = Vec::new();
+    if options.should_download() || options.should_upload() {
+        warm_up(&client, &payload_sizes, &options, &budget)?;
+    }
 
     if options.should_download() {
         measurements.extend(run_tests(
             &client,
-            test_download,
+            download,
             TestType::Download,
             payload_sizes.clone(),
-            options.nr_tests,
-            options.output_format,
-            options.disable_dynamic_max_payload_size,
-        ));
+            &options,
+            on_progress.as_ref(),
+            &budget,
+        )?);
     }
 
     if options.should_upload() {
         measurements.extend(run_tests(
             &client,
-            test_upload,
+            upload,
             TestType::Upload,
             payload_sizes.clone(),
-            options.nr_tests,
-            options.output_format,
-            options.disable_dynamic_max_payload_size,
-        ));
+            &options,
+            on_progress.as_ref(),
+            &budget,
+        )?);
     }
 
-    log_measurements(
-        &measurements,
-        payload_sizes,
-        options.verbose,
-        options.output_format,
-    );
-    measurements
+    Ok(SpeedTestResult {
+        metadata,
+        server_location,
+        client_ip,
+        ip_family: options.ip_family(),
+        latency_measurements,
+        avg_latency,
+        latency_timestamp,
+        jitter_ms,
+        measurements,
+        truncated: budget.was_exhausted(),
+        packet_loss,
+    })
+}
+
+/// Runs `options.warmup` downloads with the smallest payload size to establish the connection
+/// before measuring, their results are discarded
+fn warm_up(
+    client: &Client,
+    payload_sizes: &[usize],
+    options: &SpeedTestCLIOptions,
+    budget: &TimeBudget,
+) -> Result<(), SpeedTestError> {
+    let Some(&payload_size) = payload_sizes.iter().min() else {
+        return Ok(());
+    };
+    for i in 0..options.warmup {
+        if budget.is_cancelled() || budget.is_exhausted() {
+            break;
+        }
+        log::debug!(
+            "running warm-up download {}/{} with payload_size {payload_size}",
+            i + 1,
+            options.warmup
+        );
+        download(client, payload_size, options, &no_progress())?;
+    }
+    Ok(())
 }
 
 pub fn run_latency_test(
     client: &Client,
+    base_url: &str,
     nr_latency_tests: u32,
     output_format: OutputFormat,
-) -> (Vec<f64>, f64) {
-    let mut measurements: Vec<f64> = Vec::new();
-    for i in 0..=nr_latency_tests {
-        if output_format == OutputFormat::StdOut {
-            print_progress("latency test", i, nr_latency_tests);
+) -> Result<(Vec<LatencySample>, Option<f64>), SpeedTestError> {
+    latency_test(
+        client,
+        base_url,
+        nr_latency_tests,
+        output_format,
+        &CancellationToken::new(),
+    )
+}
+
+fn latency_test(
+    client: &Client,
+    base_url: &str,
+    nr_latency_tests: u32,
+    output_format: OutputFormat,
+    cancellation: &CancellationToken,
+) -> Result<(Vec<LatencySample>, Option<f64>), SpeedTestError> {
+    let mut measurements: Vec<LatencySample> = Vec::new();
+    let bar = progress_bar(
+        "latency test",
+        nr_latency_tests as u64 + 1,
+        show_progress(output_format),
+    );
+    for _ in 0..=nr_latency_tests {
+        if cancellation.is_cancelled() {
+            break;
         }
-        let latency = test_latency(client);
-        measurements.push(latency);
+        let latency = test_latency(client, base_url)?;
+        measurements.push((latency, Utc::now()));
+        bar.inc(1);
     }
-    let avg_latency = measurements.iter().sum::<f64>() / measurements.len() as f64;
+    bar.finish();
+    let latencies = latencies(&measurements);
+    let avg_latency = Statistics::from_samples(latencies.clone()).mean();
 
     if output_format == OutputFormat::StdOut {
-        println!(
-            "\nAvg GET request latency {avg_latency:.2} ms (RTT excluding server processing time)\n"
-        );
+        if let Some(avg_latency) = avg_latency {
+            println!(
+                "Avg GET request latency {avg_latency:.2} ms (RTT excluding server processing time)"
+            );
+        }
+        if let Some(jitter) = calc_jitter(&latencies) {
+            println!("Jitter {jitter:.2} ms");
+        }
+        println!();
     }
-    (measurements, avg_latency)
+    Ok((measurements, avg_latency))
 }
 
-pub fn test_latency(client: &Client) -> f64 {
-    let url = &format!("{}/{}{}", BASE_URL, DOWNLOAD_URL, 0);
+pub fn test_latency(client: &Client, base_url: &str) -> Result<f64, SpeedTestError> {
+    let url = &format!("{}/{}{}", base_url, DOWNLOAD_URL, 0);
     let req_builder = client.get(url);
 
     let start = Instant::now();
-    let response = req_builder.send().expect("failed to get response");
+    let response = req_builder.send()?;
     let _status_code = response.status();
     let duration = start.elapsed().as_secs_f64() * 1_000.0;
+    latency_from_headers(duration, response.headers())
+}
+
+/// Sends `nr_requests` empty downloads one after another and counts the ones which didn't
+/// complete within `deadline` or failed without a response. Responses with an error status
+/// reached the server and are therefore not counted as lost. Stops early once `cancellation` was
+/// cancelled
+pub fn run_packet_loss_test(
+    client: &Client,
+    base_url: &str,
+    nr_requests: u32,
+    deadline: Duration,
+    output_format: OutputFormat,
+    cancellation: &CancellationToken,
+) -> PacketLoss {
+    let url = &format!("{base_url}/{DOWNLOAD_URL}0");
+    let mut packet_loss = PacketLoss::default();
+    let bar = progress_bar(
+        "loss test",
+        nr_requests as u64,
+        show_progress(output_format),
+    );
+    for _ in 0..nr_requests {
+        if cancellation.is_cancelled() {
+            break;
+        }
+        packet_loss.attempted += 1;
+        match client.get(url).timeout(deadline).send() {
+            Ok(response) => {
+                if !response.status().is_success() {
+                    log::debug!("packet loss request answered with {}", response.status());
+                }
+            }
+            Err(e) if e.is_timeout() => packet_loss.timed_out += 1,
+            Err(e) => {
+                log::debug!("packet loss request failed: {e}");
+                packet_loss.failed += 1;
+            }
+        }
+        bar.inc(1);
+    }
+    bar.finish();
 
+    if output_format == OutputFormat::StdOut {
+        println!("Packet loss {packet_loss}\n");
+    }
+    packet_loss
+}
+
+/// Subtracts the server processing time reported in the `Server-Timing` header from the
+/// measured request duration (in ms)
+pub(crate) fn latency_from_headers(
+    duration: f64,
+    headers: &reqwest::header::HeaderMap,
+) -> Result<f64, SpeedTestError> {
     let re = Regex::new(r"cfRequestDuration;dur=([\d.]+)").unwrap();
+    let server_timing = headers
+        .get("Server-Timing")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| {
+            SpeedTestError::InvalidResponse("No Server-Timing in response header".to_string())
+        })?;
     let cf_req_duration: f64 = re
-        .captures(
-            response
-                .headers()
-                .get("Server-Timing")
-                .expect("No Server-Timing in response header")
-                .to_str()
-                .unwrap(),
-        )
-        .unwrap()
-        .get(1)
-        .unwrap()
-        .as_str()
-        .parse()
-        .unwrap();
+        .captures(server_timing)
+        .and_then(|captures| captures.get(1))
+        .and_then(|duration| duration.as_str().parse().ok())
+        .ok_or_else(|| {
+            SpeedTestError::InvalidResponse(format!(
+                "Unexpected Server-Timing header: {server_timing}"
+            ))
+        })?;
     let mut req_latency = duration - cf_req_duration;
     if req_latency < 0.0 {
         // TODO investigate negative latency values
         req_latency = 0.0
     }
-    req_latency
+    Ok(req_latency)
 }
 
-const TIME_THRESHOLD: Duration = Duration::from_secs(5);
+pub(crate) const TIME_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Number of consecutive payload sizes whose avg throughput needs to be within the tolerance
+/// to stop early in adaptive mode
+const CONVERGENCE_WINDOW: usize = 2;
+
+/// Outcome of a single download or upload
+struct Transfer {
+    /// Bytes of the body transferred
+    bytes: usize,
+    bytes_per_sec: f64,
+    /// Time from sending the request until the first chunk of the body arrived, downloads only
+    ttfb_ms: Option<f64>,
+}
+
+/// Runs a single download or upload test reporting the transferred bytes to the `OnProgress`
+/// callback
+type TestFn =
+    fn(&Client, usize, &SpeedTestCLIOptions, &OnProgress) -> Result<Transfer, SpeedTestError>;
+
+/// Backoff before the first retry of a failed request, doubled for every further retry
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF_EXPONENT: u32 = 5;
+
+/// Min timeout of downloads and uploads, same as the default timeout of the reqwest client
+const DEFAULT_TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Throughput of the slowest connection expected to complete a download or upload, used to
+/// scale the timeout with the payload size. Corresponds to 1 mbit/s
+const MIN_EXPECTED_BYTES_PER_SEC: f64 = 125_000.0;
+
+/// Default size of the buffer the download response is read into
+pub const DEFAULT_READ_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Max size of the buffer the download response is read into, see `--read-chunk-bytes`
+pub const MAX_READ_CHUNK_BYTES: usize = 16 * 1024 * 1024;
 
-pub fn run_tests(
+fn run_tests(
     client: &Client,
-    test_fn: fn(&Client, usize, OutputFormat) -> f64,
+    test_fn: TestFn,
     test_type: TestType,
     payload_sizes: Vec<usize>,
-    nr_tests: u32,
-    output_format: OutputFormat,
-    disable_dynamic_max_payload_size: bool,
-) -> Vec<Measurement> {
+    options: &SpeedTestCLIOptions,
+    on_progress: Option<&ProgressHandler>,
+    budget: &TimeBudget,
+) -> Result<Vec<Measurement>, SpeedTestError> {
+    let nr_tests = options.nr_tests;
+    let shows_progress = options.shows_progress();
     let mut measurements: Vec<Measurement> = Vec::new();
+    let mut avg_throughput_per_size: Vec<f64> = Vec::new();
     for payload_size in payload_sizes {
         log::debug!("running tests for payload_size {payload_size}");
         let start = Instant::now();
+        let size_stats = Statistics::new();
+        let bytes_per_test = payload_size as u64 * options.parallel as u64;
+        let bar = progress_bar(
+            &format!("{:?} {:<5}", test_type, format_bytes(payload_size)),
+            bytes_per_test * nr_tests as u64,
+            // the verbose per request lines replace the progress bars
+            shows_progress && !options.verbose && show_progress(options.output_format),
+        );
         for i in 0..nr_tests {
-            if output_format == OutputFormat::StdOut {
-                print_progress(
-                    &format!("{:?} {:<5}", test_type, format_bytes(payload_size)),
-                    i,
-                    nr_tests,
-                );
+            if budget.is_cancelled() || budget.is_exhausted() {
+                break;
             }
-            let mbit = test_fn(client, payload_size, output_format);
+            // the progress bar is driven by the same events as the callback of library users
+            let report = || {
+                let bar = bar.clone();
+                let handler = on_progress.cloned();
+                let offset = bytes_per_test * i as u64;
+                let unit = options.unit;
+                report_progress(test_type, payload_size, move |event| {
+                    bar.set_position(offset + event.bytes_transferred as u64);
+                    bar.set_message(
+                        unit.format(compute_throughput(event.bytes_transferred, event.elapsed)),
+                    );
+                    if let Some(handler) = &handler {
+                        handler(event);
+                    }
+                })
+            };
+            let Transfer {
+                bytes_per_sec,
+                ttfb_ms,
+                ..
+            } = run_with_retries(
+                client,
+                test_fn,
+                test_type,
+                payload_size,
+                options,
+                budget,
+                report,
+            )?;
+            bar.set_position(bytes_per_test * (i as u64 + 1));
+            bar.set_message(options.unit.format(bytes_per_sec));
+            size_stats.add_throughput(bytes_per_sec);
             measurements.push(Measurement {
                 test_type,
                 payload_size,
-                mbit,
+                bytes_per_sec,
+                ttfb_ms,
+                timestamp: Utc::now(),
             });
         }
-        if output_format == OutputFormat::StdOut {
-            print_progress(
-                &format!("{:?} {:<5}", test_type, format_bytes(payload_size)),
-                nr_tests,
-                nr_tests,
-            );
-            println!()
-        }
+        bar.finish();
         let duration = start.elapsed();
+        if budget.is_cancelled() {
+            log::info!("Speed test cancelled");
+            break;
+        }
+        if budget.is_exhausted() {
+            log::info!("Exceeded max duration");
+            break;
+        }
 
         // only check TIME_THRESHOLD if dynamic max payload sizing is not disabled
-        if !disable_dynamic_max_payload_size && duration > TIME_THRESHOLD {
+        if !options.disable_dynamic_max_payload_size && duration > TIME_THRESHOLD {
             log::info!("Exceeded threshold");
             break;
         }
+
+        if let (true, Some(avg_throughput)) = (options.adaptive, size_stats.mean()) {
+            avg_throughput_per_size.push(avg_throughput);
+            if has_converged(
+                &avg_throughput_per_size,
+                CONVERGENCE_WINDOW,
+                options.adaptive_tolerance / 100.0,
+            ) {
+                let converged = options
+                    .unit
+                    .format(*avg_throughput_per_size.last().unwrap());
+                log::info!("{test_type:?} converged at {converged}");
+                if shows_progress {
+                    println!("{test_type:?} converged at {converged}");
+                }
+                break;
+            }
+        }
     }
-    measurements
+    Ok(measurements)
 }
 
-pub fn test_upload(client: &Client, payload_size_bytes: usize, output_format: OutputFormat) -> f64 {
-    let url = &format!("{BASE_URL}/{UPLOAD_URL}");
-    let payload: Vec<u8> = vec![1; payload_size_bytes];
-    let req_builder = client.post(url).body(payload);
-    let (status_code, mbits, duration) = {
-        let start = Instant::now();
-        let response = req_builder.send().expect("failed to get response");
-        let status_code = response.status();
-        let duration = start.elapsed();
-        let mbits = (payload_size_bytes as f64 * 8.0 / 1_000_000.0) / duration.as_secs_f64();
-        (status_code, mbits, duration)
-    };
-    if output_format == OutputFormat::StdOut {
-        print_current_speed(mbits, duration, status_code, payload_size_bytes);
+/// Runs `run_parallel` and retries it up to `options.retries` times with exponential backoff if
+/// it failed with a retryable error. `report` creates a fresh progress callback for every attempt
+fn run_with_retries(
+    client: &Client,
+    test_fn: TestFn,
+    test_type: TestType,
+    payload_size: usize,
+    options: &SpeedTestCLIOptions,
+    budget: &TimeBudget,
+    report: impl Fn() -> OnProgress,
+) -> Result<Transfer, SpeedTestError> {
+    let mut attempt = 0;
+    loop {
+        match run_parallel(client, test_fn, payload_size, options, &report()) {
+            Err(e) if e.is_retryable() && attempt < options.retries && !budget.is_cancelled() => {
+                let backoff = RETRY_BACKOFF * 2_u32.pow(attempt.min(MAX_BACKOFF_EXPONENT));
+                attempt += 1;
+                log::warn!(
+                    "{test_type:?} {} failed, retrying in {}ms ({attempt}/{}): {e}",
+                    format_bytes(payload_size),
+                    backoff.as_millis(),
+                    options.retries
+                );
+                thread::sleep(backoff);
+            }
+            outcome => return outcome,
+        }
+    }
+}
+
+/// Runs `test_fn` on `options.parallel` concurrent connections and returns the total bytes
+/// transferred by all of them divided by the wall-clock time until the last one completed, along
+/// with their avg TTFB. Failed connections are skipped with a warning, the first error is
+/// returned if all of them failed
+fn run_parallel(
+    client: &Client,
+    test_fn: TestFn,
+    payload_size: usize,
+    options: &SpeedTestCLIOptions,
+    on_progress: &OnProgress,
+) -> Result<Transfer, SpeedTestError> {
+    let parallel = options.parallel;
+    if parallel <= 1 {
+        return test_fn(client, payload_size, options, on_progress);
     }
-    mbits
+    let start = Instant::now();
+    let outcomes: Vec<Result<Transfer, SpeedTestError>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..parallel)
+            .map(|_| scope.spawn(|| test_fn(client, payload_size, options, on_progress)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("test thread panicked"))
+            .collect()
+    });
+    let duration = start.elapsed();
+    let mut results: Vec<Transfer> = Vec::new();
+    let mut first_error = None;
+    for outcome in outcomes {
+        match outcome {
+            Ok(transfer) => results.push(transfer),
+            Err(e) => {
+                log::debug!("parallel connection failed: {e}");
+                if first_error.is_none() {
+                    first_error = Some(e);
+                }
+            }
+        }
+    }
+    if results.is_empty() {
+        if let Some(e) = first_error {
+            return Err(e);
+        }
+    }
+    let failed = parallel as usize - results.len();
+    if failed > 0 {
+        log::warn!(
+            "{failed} of {parallel} parallel connections failed for payload size {}",
+            format_bytes(payload_size)
+        );
+    }
+    let ttfbs = Statistics::from_samples(results.iter().filter_map(|t| t.ttfb_ms).collect());
+    let bytes = results.iter().map(|t| t.bytes).sum();
+    Ok(Transfer {
+        bytes,
+        bytes_per_sec: compute_throughput(bytes, duration),
+        ttfb_ms: ttfbs.mean(),
+    })
 }
 
+/// Tests the upload speed with the given payload size and returns the throughput in bytes/s
+pub fn test_upload(
+    client: &Client,
+    payload_size_bytes: usize,
+    options: &SpeedTestCLIOptions,
+) -> Result<f64, SpeedTestError> {
+    upload(client, payload_size_bytes, options, &no_progress()).map(|t| t.bytes_per_sec)
+}
+
+fn upload(
+    client: &Client,
+    payload_size_bytes: usize,
+    options: &SpeedTestCLIOptions,
+    on_progress: &OnProgress,
+) -> Result<Transfer, SpeedTestError> {
+    let url = &format!("{}/{UPLOAD_URL}", options.server_url);
+    // stream the body so large payloads are never buffered in memory
+    let payload = ProgressReader::new(
+        RateLimitedReader::new(
+            RandomPayload::new(payload_size_bytes, options.seed),
+            rate_limiter(options),
+        ),
+        Arc::clone(on_progress),
+    );
+    let req_builder = client
+        .post(url)
+        .timeout(request_timeout(
+            TestType::Upload,
+            payload_size_bytes,
+            options,
+        ))
+        .body(Body::sized(payload, payload_size_bytes as u64));
+    let start = Instant::now();
+    let response = req_builder.send()?.error_for_status()?;
+    let duration = start.elapsed();
+    RequestLog {
+        test_type: TestType::Upload,
+        url,
+        bytes: payload_size_bytes,
+        payload_size_bytes,
+        duration,
+        ttfb: None,
+        read_chunk_bytes: None,
+        status_code: response.status(),
+    }
+    .log(options);
+    Ok(Transfer {
+        bytes: payload_size_bytes,
+        bytes_per_sec: compute_throughput(payload_size_bytes, duration),
+        ttfb_ms: None,
+    })
+}
+
+/// Tests the download speed with the given payload size and returns the throughput in bytes/s
 pub fn test_download(
     client: &Client,
     payload_size_bytes: usize,
-    output_format: OutputFormat,
-) -> f64 {
-    let url = &format!("{BASE_URL}/{DOWNLOAD_URL}{payload_size_bytes}");
-    let req_builder = client.get(url);
-    let (status_code, mbits, duration) = {
-        let response = req_builder.send().expect("failed to get response");
-        let status_code = response.status();
-        let start = Instant::now();
-        let _res_bytes = response.bytes();
-        let duration = start.elapsed();
-        let mbits = (payload_size_bytes as f64 * 8.0 / 1_000_000.0) / duration.as_secs_f64();
-        (status_code, mbits, duration)
-    };
-    if output_format == OutputFormat::StdOut {
-        print_current_speed(mbits, duration, status_code, payload_size_bytes);
+    options: &SpeedTestCLIOptions,
+) -> Result<f64, SpeedTestError> {
+    download(client, payload_size_bytes, options, &no_progress()).map(|t| t.bytes_per_sec)
+}
+
+fn download(
+    client: &Client,
+    payload_size_bytes: usize,
+    options: &SpeedTestCLIOptions,
+    on_progress: &OnProgress,
+) -> Result<Transfer, SpeedTestError> {
+    let url = &format!("{}/{DOWNLOAD_URL}{payload_size_bytes}", options.server_url);
+    let request_start = Instant::now();
+    let response = client
+        .get(url)
+        .timeout(request_timeout(
+            TestType::Download,
+            payload_size_bytes,
+            options,
+        ))
+        .send()?
+        .error_for_status()?;
+    let status_code = response.status();
+    let mut response = RateLimitedReader::new(response, rate_limiter(options));
+    let start = Instant::now();
+    let mut ttfb = None;
+    let mut received = 0;
+    let mut buffer = vec![0; options.read_chunk_bytes];
+    loop {
+        let bytes = response.read(&mut buffer)?;
+        if bytes == 0 {
+            break;
+        }
+        if ttfb.is_none() {
+            ttfb = Some(request_start.elapsed());
+        }
+        received += bytes;
+        on_progress(bytes);
     }
-    mbits
+    let duration = start.elapsed();
+    RequestLog {
+        test_type: TestType::Download,
+        url,
+        bytes: received,
+        payload_size_bytes,
+        duration,
+        ttfb,
+        read_chunk_bytes: Some(options.read_chunk_bytes),
+        status_code,
+    }
+    .log(options);
+    let ttfb_ms = ttfb.map(|ttfb| ttfb.as_secs_f64() * 1_000.0);
+    Ok(Transfer {
+        bytes: received,
+        bytes_per_sec: compute_throughput(received, duration),
+        ttfb_ms,
+    })
 }
 
-fn print_current_speed(
-    mbits: f64,
+/// Details of a single download or upload request
+struct RequestLog<'a> {
+    test_type: TestType,
+    url: &'a str,
+    bytes: usize,
+    payload_size_bytes: usize,
     duration: Duration,
+    ttfb: Option<Duration>,
+    /// Size of the buffer the response was read into, only set for downloads
+    read_chunk_bytes: Option<usize>,
     status_code: StatusCode,
+}
+
+impl RequestLog<'_> {
+    /// Prints the request with `--verbose`, otherwise logs it at debug level
+    fn log(&self, options: &SpeedTestCLIOptions) {
+        let ttfb = self
+            .ttfb
+            .map(|ttfb| format!(", ttfb {}ms", ttfb.as_millis()))
+            .unwrap_or_default();
+        let read_chunk = self
+            .read_chunk_bytes
+            .map(|bytes| format!(", read chunk {bytes} bytes"))
+            .unwrap_or_default();
+        let line = format!(
+            "[{} {}] {}/{} bytes in {}ms -> {} (status: {}{ttfb}{read_chunk}, {})",
+            format!("{:?}", self.test_type).to_lowercase(),
+            format_bytes(self.payload_size_bytes),
+            self.bytes,
+            self.payload_size_bytes,
+            self.duration.as_millis(),
+            options
+                .unit
+                .format(compute_throughput(self.bytes, self.duration)),
+            self.status_code,
+            self.url,
+        );
+        if options.verbose && options.shows_progress() {
+            println!("{line}");
+        } else {
+            log::debug!("{line}");
+        }
+    }
+}
+
+/// Returns the timeout of a download or upload with the given payload size: `--download-timeout`
+/// or `--upload-timeout` if given, otherwise `transfer_timeout` with `--timeout-secs` as minimum
+pub(crate) fn request_timeout(
+    test_type: TestType,
     payload_size_bytes: usize,
-) {
-    print!(
-        "  {:>6.2} mbit/s | {:>5} in {:>4}ms -> status: {}  ",
-        mbits,
-        format_bytes(payload_size_bytes),
-        duration.as_millis(),
-        status_code
-    );
+    options: &SpeedTestCLIOptions,
+) -> Duration {
+    let phase_timeout = match test_type {
+        TestType::Download => options.download_timeout,
+        TestType::Upload => options.upload_timeout,
+        TestType::Latency => None,
+    };
+    match phase_timeout {
+        Some(timeout_secs) => Duration::from_secs(timeout_secs),
+        None => transfer_timeout(
+            payload_size_bytes,
+            options
+                .timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TRANSFER_TIMEOUT),
+        ),
+    }
 }
 
-pub fn fetch_metadata(client: &Client) -> Metadata {
-    let url = &format!("{}/{}{}", BASE_URL, DOWNLOAD_URL, 0);
-    let headers = client
-        .get(url)
-        .send()
-        .expect("failed to get response")
-        .headers()
-        .to_owned();
+/// Scales the timeout with the payload size so even large payloads can complete on slow
+/// connections, `min_timeout` is used for smaller payloads
+pub fn transfer_timeout(payload_size_bytes: usize, min_timeout: Duration) -> Duration {
+    let expected = payload_size_bytes as f64 / MIN_EXPECTED_BYTES_PER_SEC;
+    min_timeout.max(Duration::from_secs_f64(expected))
+}
+
+/// Returns the limiter of a single connection for `--max-mbps`, the cap is split evenly
+/// between the parallel connections
+fn rate_limiter(options: &SpeedTestCLIOptions) -> Option<RateLimiter> {
+    options.max_mbps.map(|max_mbps| {
+        RateLimiter::new(Unit::Mbps.to_bytes_per_sec(max_mbps) / options.parallel as f64)
+    })
+}
+
+fn no_progress() -> OnProgress {
+    Arc::new(|_| {})
+}
+
+/// Calculates the throughput in bytes/s for the given amount of bytes transferred in `duration`
+pub(crate) fn compute_throughput(bytes: usize, duration: Duration) -> f64 {
+    bytes as f64 / duration.as_secs_f64()
+}
+
+pub fn fetch_metadata(client: &Client, base_url: &str) -> Result<Metadata, SpeedTestError> {
+    let url = &format!("{}/{}{}", base_url, DOWNLOAD_URL, 0);
+    let response = client.get(url).send()?;
+    Ok(metadata_from_response(
+        response.version(),
+        response.headers(),
+    ))
+}
+
+pub(crate) fn metadata_from_response(
+    version: Version,
+    headers: &reqwest::header::HeaderMap,
+) -> Metadata {
     Metadata {
-        city: extract_header_value(&headers, "cf-meta-city", "City N/A"),
-        country: extract_header_value(&headers, "cf-meta-country", "Country N/A"),
-        ip: extract_header_value(&headers, "cf-meta-ip", "IP N/A"),
-        asn: extract_header_value(&headers, "cf-meta-asn", "ASN N/A"),
-        colo: extract_header_value(&headers, "cf-meta-colo", "Colo N/A"),
+        city: extract_header_value(headers, "cf-meta-city", "City N/A"),
+        country: extract_header_value(headers, "cf-meta-country", "Country N/A"),
+        ip: extract_header_value(headers, "cf-meta-ip", "IP N/A"),
+        asn: extract_header_value(headers, "cf-meta-asn", "ASN N/A"),
+        colo: extract_header_value(headers, "cf-meta-colo", "Colo N/A"),
+        http_version: format!("{version:?}"),
+    }
+}
+
+#[derive(Deserialize)]
+struct Location {
+    iata: String,
+    city: String,
+}
+
+/// Connection details reported by the trace endpoint
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trace {
+    /// City and colo code of the datacenter serving the requests, e.g. "Frankfurt (FRA)"
+    pub server_location: Option<String>,
+    /// Public IP address of the client as seen by the server
+    pub client_ip: Option<String>,
+}
+
+/// Fetches the colo serving the requests and the client IP from the trace endpoint and resolves
+/// the city name of the colo. Both are `None` if the trace request fails
+pub fn fetch_trace(client: &Client, base_url: &str) -> Trace {
+    let trace = client
+        .get(format!("{base_url}/{TRACE_URL}"))
+        .send()
+        .and_then(|response| response.text());
+    let mut trace = match trace {
+        Ok(trace) => parse_trace(&trace),
+        Err(e) => {
+            log::warn!("failed to fetch trace: {e}");
+            return Trace::default();
+        }
+    };
+    let server_location = trace.remove("colo").map(|colo| {
+        let locations = client
+            .get(format!("{base_url}/{LOCATIONS_URL}"))
+            .send()
+            .and_then(|response| response.text())
+            .ok();
+        format_server_location(colo, locations.as_deref())
+    });
+    Trace {
+        server_location,
+        client_ip: trace.remove("ip"),
+    }
+}
+
+/// Fetches the colo serving the requests from the trace endpoint and resolves its city name.
+/// Returns `None` if the trace request fails
+pub fn fetch_server_location(client: &Client, base_url: &str) -> Option<String> {
+    fetch_trace(client, base_url).server_location
+}
+
+/// Parses the `key=value` lines returned by the trace endpoint
+pub(crate) fn parse_trace(trace: &str) -> HashMap<String, String> {
+    trace
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// Formats the colo code as "City (COLO)" if the city can be found in the locations json
+pub(crate) fn format_server_location(colo: String, locations: Option<&str>) -> String {
+    let city = locations
+        .and_then(|locations| serde_json::from_str::<Vec<Location>>(locations).ok())
+        .and_then(|locations| locations.into_iter().find(|l| l.iata == colo))
+        .map(|location| location.city);
+    match city {
+        Some(city) => format!("{city} ({colo})"),
+        None => colo,
     }
 }
 
@@ -0,0 +1,308 @@
+use crate::error::SpeedTestError;
+use crate::measurements::{calc_jitter, Measurement};
+use crate::payload::{RandomPayload, CHUNK_BYTES};
+use crate::speedtest::{
+    compute_throughput, format_server_location, latencies, latency_from_headers,
+    metadata_from_response, parse_trace, request_timeout, LatencySample, Metadata, SpeedTestResult,
+    TestType, Trace, DEFAULT_READ_CHUNK_BYTES, DOWNLOAD_URL, LOCATIONS_URL, TIME_THRESHOLD,
+    TRACE_URL, UPLOAD_URL,
+};
+use crate::stats::Statistics;
+use crate::SpeedTestCLIOptions;
+use chrono::Utc;
+use futures_util::stream::{self, Stream};
+use log;
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{Body, Client, RequestBuilder};
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+/// Async variant of `speedtest::speed_test` using the non-blocking reqwest client.
+/// No progress is printed, regardless of the configured output format, and no TTFB is measured.
+/// The `parallel`, `adaptive`, `max_duration`, `retries`, `measure_loss`, `max_mbps` and
+/// `read_chunk_bytes` options are not supported, setting them fails with
+/// `SpeedTestError::InvalidOptions`.
+/// Downloads and uploads use the same timeouts as `speedtest::speed_test`, `timeout_secs` is
+/// applied to every other request. Dropping the returned future aborts any in-flight request.
+pub async fn speed_test_async(
+    client: Client,
+    options: SpeedTestCLIOptions,
+) -> Result<SpeedTestResult, SpeedTestError> {
+    options.validate().map_err(SpeedTestError::InvalidOptions)?;
+    check_supported(&options)?;
+    let timeout = options.timeout_secs.map(Duration::from_secs);
+    let base_url = &options.server_url;
+    let metadata = fetch_metadata(&client, base_url, timeout).await?;
+    let Trace {
+        server_location,
+        client_ip,
+    } = fetch_trace(&client, base_url, timeout).await;
+    let (latency_measurements, avg_latency) = if options.no_latency {
+        (Vec::new(), None)
+    } else {
+        run_latency_test(&client, base_url, options.nr_latency_tests, timeout).await?
+    };
+    let latency_timestamp = Utc::now();
+    let jitter_ms = calc_jitter(&latencies(&latency_measurements));
+    let payload_sizes = options.payload_sizes();
+    let mut measurements = Vec::new();
+    if options.should_download() || options.should_upload() {
+        if let Some(&payload_size) = payload_sizes.iter().min() {
+            for i in 0..options.warmup {
+                log::debug!(
+                    "running warm-up download {}/{} with payload_size {payload_size}",
+                    i + 1,
+                    options.warmup
+                );
+                let download_timeout = request_timeout(TestType::Download, payload_size, &options);
+                test_download(&client, base_url, payload_size, Some(download_timeout)).await?;
+            }
+        }
+    }
+
+    if options.should_download() {
+        measurements
+            .extend(run_tests(&client, TestType::Download, payload_sizes.clone(), &options).await?);
+    }
+
+    if options.should_upload() {
+        measurements
+            .extend(run_tests(&client, TestType::Upload, payload_sizes.clone(), &options).await?);
+    }
+
+    Ok(SpeedTestResult {
+        metadata,
+        server_location,
+        client_ip,
+        ip_family: options.ip_family(),
+        latency_measurements,
+        avg_latency,
+        latency_timestamp,
+        jitter_ms,
+        measurements,
+        truncated: false,
+        packet_loss: None,
+    })
+}
+
+/// Fails if one of the options which are not supported by the async variant is set
+fn check_supported(options: &SpeedTestCLIOptions) -> Result<(), SpeedTestError> {
+    let unsupported: Vec<&str> = [
+        ("--parallel", options.parallel > 1),
+        ("--adaptive", options.adaptive),
+        ("--max-duration", options.max_duration.is_some()),
+        ("--retries", options.retries > 0),
+        ("--measure-loss", options.measure_loss),
+        ("--max-mbps", options.max_mbps.is_some()),
+        (
+            "--read-chunk-bytes",
+            options.read_chunk_bytes != DEFAULT_READ_CHUNK_BYTES,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, is_set)| is_set.then_some(name))
+    .collect();
+    if unsupported.is_empty() {
+        return Ok(());
+    }
+    Err(SpeedTestError::InvalidOptions(format!(
+        "{} not supported by the async speed test",
+        unsupported.join(", ")
+    )))
+}
+
+fn with_timeout(req_builder: RequestBuilder, timeout: Option<Duration>) -> RequestBuilder {
+    match timeout {
+        Some(timeout) => req_builder.timeout(timeout),
+        None => req_builder,
+    }
+}
+
+pub async fn run_latency_test(
+    client: &Client,
+    base_url: &str,
+    nr_latency_tests: u32,
+    timeout: Option<Duration>,
+) -> Result<(Vec<LatencySample>, Option<f64>), SpeedTestError> {
+    let mut measurements: Vec<LatencySample> = Vec::new();
+    for _ in 0..=nr_latency_tests {
+        let latency = test_latency(client, base_url, timeout).await?;
+        measurements.push((latency, Utc::now()));
+    }
+    let latencies = latencies(&measurements);
+    let avg_latency = Statistics::from_samples(latencies).mean();
+    Ok((measurements, avg_latency))
+}
+
+pub async fn test_latency(
+    client: &Client,
+    base_url: &str,
+    timeout: Option<Duration>,
+) -> Result<f64, SpeedTestError> {
+    let url = &format!("{}/{}{}", base_url, DOWNLOAD_URL, 0);
+    let req_builder = with_timeout(client.get(url), timeout);
+
+    let start = Instant::now();
+    let response = req_builder.send().await?;
+    let duration = start.elapsed().as_secs_f64() * 1_000.0;
+    latency_from_headers(duration, response.headers())
+}
+
+pub async fn run_tests(
+    client: &Client,
+    test_type: TestType,
+    payload_sizes: Vec<usize>,
+    options: &SpeedTestCLIOptions,
+) -> Result<Vec<Measurement>, SpeedTestError> {
+    let base_url = &options.server_url;
+    let mut measurements: Vec<Measurement> = Vec::new();
+    for payload_size in payload_sizes {
+        log::debug!("running tests for payload_size {payload_size}");
+        let start = Instant::now();
+        let timeout = Some(request_timeout(test_type, payload_size, options));
+        for _ in 0..options.nr_tests {
+            let bytes_per_sec = match test_type {
+                TestType::Download => {
+                    test_download(client, base_url, payload_size, timeout).await?
+                }
+                TestType::Upload => {
+                    test_upload(client, base_url, payload_size, options.seed, timeout).await?
+                }
+                TestType::Latency => unreachable!("latency is measured by run_latency_test"),
+            };
+            measurements.push(Measurement {
+                test_type,
+                payload_size,
+                bytes_per_sec,
+                ttfb_ms: None,
+                timestamp: Utc::now(),
+            });
+        }
+        let duration = start.elapsed();
+
+        // only check TIME_THRESHOLD if dynamic max payload sizing is not disabled
+        if !options.disable_dynamic_max_payload_size && duration > TIME_THRESHOLD {
+            log::info!("Exceeded threshold");
+            break;
+        }
+    }
+    Ok(measurements)
+}
+
+/// Tests the upload speed with the given payload size and returns the throughput in bytes/s.
+/// The pseudo-random payload is generated from `seed` while it's streamed to the server
+pub async fn test_upload(
+    client: &Client,
+    base_url: &str,
+    payload_size_bytes: usize,
+    seed: Option<u64>,
+    timeout: Option<Duration>,
+) -> Result<f64, SpeedTestError> {
+    let url = &format!("{base_url}/{UPLOAD_URL}");
+    let payload = payload_stream(RandomPayload::new(payload_size_bytes, seed));
+    let req_builder = with_timeout(
+        client
+            .post(url)
+            .header(CONTENT_LENGTH, payload_size_bytes)
+            .body(Body::wrap_stream(payload)),
+        timeout,
+    );
+    let start = Instant::now();
+    let _response = req_builder.send().await?.error_for_status()?;
+    Ok(compute_throughput(payload_size_bytes, start.elapsed()))
+}
+
+/// Streams `payload` in chunks of at most `CHUNK_BYTES`, which are only generated once the
+/// previous chunk was sent
+fn payload_stream(mut payload: RandomPayload) -> impl Stream<Item = io::Result<Vec<u8>>> {
+    stream::iter(std::iter::from_fn(move || {
+        let mut chunk = vec![0; CHUNK_BYTES];
+        match payload.read(&mut chunk) {
+            Ok(0) => None,
+            Ok(len) => {
+                chunk.truncate(len);
+                Some(Ok(chunk))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }))
+}
+
+/// Tests the download speed with the given payload size and returns the throughput in bytes/s.
+/// Fails if the body is shorter than the payload size
+pub async fn test_download(
+    client: &Client,
+    base_url: &str,
+    payload_size_bytes: usize,
+    timeout: Option<Duration>,
+) -> Result<f64, SpeedTestError> {
+    let url = &format!("{base_url}/{DOWNLOAD_URL}{payload_size_bytes}");
+    let req_builder = with_timeout(client.get(url), timeout);
+    let response = req_builder.send().await?.error_for_status()?;
+    let start = Instant::now();
+    let body = response.bytes().await?;
+    let duration = start.elapsed();
+    if body.len() < payload_size_bytes {
+        return Err(SpeedTestError::InvalidResponse(format!(
+            "download of {payload_size_bytes} bytes ended after {} bytes",
+            body.len()
+        )));
+    }
+    Ok(compute_throughput(body.len(), duration))
+}
+
+pub async fn fetch_metadata(
+    client: &Client,
+    base_url: &str,
+    timeout: Option<Duration>,
+) -> Result<Metadata, SpeedTestError> {
+    let url = &format!("{}/{}{}", base_url, DOWNLOAD_URL, 0);
+    let response = with_timeout(client.get(url), timeout).send().await?;
+    Ok(metadata_from_response(
+        response.version(),
+        response.headers(),
+    ))
+}
+
+pub async fn fetch_trace(client: &Client, base_url: &str, timeout: Option<Duration>) -> Trace {
+    let trace = match with_timeout(client.get(format!("{base_url}/{TRACE_URL}")), timeout)
+        .send()
+        .await
+    {
+        Ok(response) => response.text().await,
+        Err(e) => Err(e),
+    };
+    let mut trace = match trace {
+        Ok(trace) => parse_trace(&trace),
+        Err(e) => {
+            log::warn!("failed to fetch trace: {e}");
+            return Trace::default();
+        }
+    };
+    let server_location = match trace.remove("colo") {
+        Some(colo) => {
+            let locations =
+                match with_timeout(client.get(format!("{base_url}/{LOCATIONS_URL}")), timeout)
+                    .send()
+                    .await
+                {
+                    Ok(response) => response.text().await.ok(),
+                    Err(_) => None,
+                };
+            Some(format_server_location(colo, locations.as_deref()))
+        }
+        None => None,
+    };
+    Trace {
+        server_location,
+        client_ip: trace.remove("ip"),
+    }
+}
+
+pub async fn fetch_server_location(
+    client: &Client,
+    base_url: &str,
+    timeout: Option<Duration>,
+) -> Option<String> {
+    fetch_trace(client, base_url, timeout).await.server_location
+}
@@ -0,0 +1,154 @@
+use crate::Unit;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Calculates the `p`th percentile (0-100) of the given samples using linear interpolation
+/// between the closest ranks. Returns `None` for an empty sample set
+pub fn percentile(samples: &[f64], p: f64) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Less));
+
+    let rank = (p.clamp(0.0, 100.0) / 100.0) * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let fraction = rank - lower as f64;
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * fraction)
+}
+
+/// Returns whether the last `window` values all lie within the relative `tolerance`
+/// (e.g. 0.05 for 5%) of their mean. Returns `false` if there are less than `window` values
+pub fn has_converged(values: &[f64], window: usize, tolerance: f64) -> bool {
+    if window == 0 || values.len() < window {
+        return false;
+    }
+    let last_values = &values[values.len() - window..];
+    let mean = last_values.iter().sum::<f64>() / window as f64;
+    if mean <= 0.0 {
+        return false;
+    }
+    last_values
+        .iter()
+        .all(|value| ((value - mean) / mean).abs() <= tolerance)
+}
+
+/// Returns whether the standard deviation of the throughput samples exceeds `max_variation`
+/// (e.g. 0.25 for 25%) of their mean. Returns `false` if there are less than 2 samples
+pub fn is_unstable(stats: &Statistics, max_variation: f64) -> bool {
+    if stats.len() < 2 {
+        return false;
+    }
+    match (stats.mean_mbps(), stats.std_dev_mbps()) {
+        (Some(mean), Some(std_dev)) if mean > 0.0 => std_dev > mean * max_variation,
+        _ => false,
+    }
+}
+
+/// Throughput statistics of a set of transfers. Samples can be added concurrently, e.g. from
+/// the threads of parallel connections
+#[derive(Debug, Default)]
+pub struct Statistics {
+    /// Throughput of every sample in bytes/s
+    samples: Mutex<Vec<f64>>,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Statistics of samples in any unit, e.g. latencies in ms. Only the methods without a unit
+    /// in their name are meaningful for samples which aren't throughputs in bytes/s
+    pub fn from_samples(samples: Vec<f64>) -> Self {
+        Self {
+            samples: Mutex::new(samples),
+        }
+    }
+
+    /// Adds a sample of `bytes` transferred in `duration`
+    pub fn add_sample(&self, bytes: usize, duration: Duration) {
+        self.add_throughput(bytes as f64 / duration.as_secs_f64());
+    }
+
+    /// Adds a sample with the given throughput in bytes/s
+    pub fn add_throughput(&self, bytes_per_sec: f64) {
+        self.samples().push(bytes_per_sec);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Mean throughput in bytes/s, `None` without samples
+    pub fn mean(&self) -> Option<f64> {
+        let samples = self.samples();
+        if samples.is_empty() {
+            return None;
+        }
+        Some(samples.iter().sum::<f64>() / samples.len() as f64)
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.samples().iter().copied().reduce(f64::min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.samples().iter().copied().reduce(f64::max)
+    }
+
+    /// Median of the samples, `None` without samples
+    pub fn median(&self) -> Option<f64> {
+        percentile(&self.samples(), 50.0)
+    }
+
+    /// First and third quartile as the medians of the lower and upper half of the sorted
+    /// samples, the median of an odd number of samples belongs to the lower half. `None` if
+    /// there are less than 4 samples
+    pub fn quartiles(&self) -> Option<(f64, f64)> {
+        let samples = self.samples();
+        if samples.len() < 4 {
+            return None;
+        }
+        let mut sorted = samples.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Less));
+        let (lower, upper) = sorted.split_at(sorted.len().div_ceil(2));
+        Some((percentile(lower, 50.0)?, percentile(upper, 50.0)?))
+    }
+
+    pub fn mean_mbps(&self) -> Option<f64> {
+        self.mean().map(|mean| Unit::Mbps.convert(mean))
+    }
+
+    pub fn max_mbps(&self) -> Option<f64> {
+        self.max().map(|max| Unit::Mbps.convert(max))
+    }
+
+    pub fn min_mbps(&self) -> Option<f64> {
+        self.min().map(|min| Unit::Mbps.convert(min))
+    }
+
+    /// Population standard deviation of the throughput in mbit/s, `None` without samples
+    pub fn std_dev_mbps(&self) -> Option<f64> {
+        let mean = self.mean_mbps()?;
+        let samples = self.samples();
+        let variance = samples
+            .iter()
+            .map(|sample| (Unit::Mbps.convert(*sample) - mean).powi(2))
+            .sum::<f64>()
+            / samples.len() as f64;
+        Some(variance.sqrt())
+    }
+
+    fn samples(&self) -> MutexGuard<'_, Vec<f64>> {
+        // the samples stay consistent even if a thread panicked while holding the lock
+        self.samples
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
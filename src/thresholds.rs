@@ -0,0 +1,165 @@
+use crate::baseline::{BaselineDelta, Metric};
+use crate::speedtest::{SpeedTestResult, TestType};
+use crate::stats::Statistics;
+use crate::SpeedTestCLIOptions;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+/// Limits the measured connection needs to satisfy, throughput limits are in mbit/s
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Thresholds {
+    pub min_download_mbps: Option<f64>,
+    pub min_upload_mbps: Option<f64>,
+    pub max_latency_ms: Option<f64>,
+}
+
+impl Thresholds {
+    pub fn from_options(options: &SpeedTestCLIOptions) -> Self {
+        Self {
+            min_download_mbps: options.min_download,
+            min_upload_mbps: options.min_upload,
+            max_latency_ms: options.max_latency,
+        }
+    }
+}
+
+/// A threshold which was not met by the measured connection
+#[derive(Clone, Debug, PartialEq)]
+pub enum ThresholdViolation {
+    Download {
+        measured_mbps: f64,
+        min_mbps: f64,
+    },
+    Upload {
+        measured_mbps: f64,
+        min_mbps: f64,
+    },
+    Latency {
+        measured_ms: f64,
+        max_ms: f64,
+    },
+    /// The metric got worse by more than `max_percent` compared to the baseline
+    Regression {
+        metric: Metric,
+        regression_percent: f64,
+        max_percent: f64,
+    },
+}
+
+impl Display for ThresholdViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Download {
+                measured_mbps,
+                min_mbps,
+            } => write!(
+                f,
+                "download {measured_mbps:.2} mbit/s is below the minimum of {min_mbps:.2} mbit/s"
+            ),
+            Self::Upload {
+                measured_mbps,
+                min_mbps,
+            } => write!(
+                f,
+                "upload {measured_mbps:.2} mbit/s is below the minimum of {min_mbps:.2} mbit/s"
+            ),
+            Self::Latency {
+                measured_ms,
+                max_ms,
+            } => write!(
+                f,
+                "latency {measured_ms:.2} ms is above the maximum of {max_ms:.2} ms"
+            ),
+            Self::Regression {
+                metric: Metric::Latency,
+                regression_percent,
+                max_percent,
+            } => write!(
+                f,
+                "latency rose by {regression_percent:.1}% compared to the baseline, more than the allowed {max_percent:.1}%"
+            ),
+            Self::Regression {
+                metric,
+                regression_percent,
+                max_percent,
+            } => write!(
+                f,
+                "{metric} dropped by {regression_percent:.1}% compared to the baseline, more than the allowed {max_percent:.1}%"
+            ),
+        }
+    }
+}
+
+/// Checks the result against the thresholds. Throughput is compared using the best avg
+/// throughput of all payload sizes, latency using the avg latency. Thresholds of test types
+/// which were not measured are ignored
+pub fn check_thresholds(
+    result: &SpeedTestResult,
+    thresholds: &Thresholds,
+) -> Vec<ThresholdViolation> {
+    let mut violations = Vec::new();
+    if let (Some(min_mbps), Some(measured_mbps)) = (
+        thresholds.min_download_mbps,
+        best_avg_mbps(result, TestType::Download),
+    ) {
+        if measured_mbps < min_mbps {
+            violations.push(ThresholdViolation::Download {
+                measured_mbps,
+                min_mbps,
+            });
+        }
+    }
+    if let (Some(min_mbps), Some(measured_mbps)) = (
+        thresholds.min_upload_mbps,
+        best_avg_mbps(result, TestType::Upload),
+    ) {
+        if measured_mbps < min_mbps {
+            violations.push(ThresholdViolation::Upload {
+                measured_mbps,
+                min_mbps,
+            });
+        }
+    }
+    if let (Some(max_ms), Some(measured_ms)) = (thresholds.max_latency_ms, result.avg_latency) {
+        if measured_ms > max_ms {
+            violations.push(ThresholdViolation::Latency {
+                measured_ms,
+                max_ms,
+            });
+        }
+    }
+    violations
+}
+
+/// Returns a violation for every metric which got worse by more than `max_percent` compared to
+/// the baseline
+pub fn check_regressions(deltas: &[BaselineDelta], max_percent: f64) -> Vec<ThresholdViolation> {
+    deltas
+        .iter()
+        .filter(|delta| delta.regression_percent() > max_percent)
+        .map(|delta| ThresholdViolation::Regression {
+            metric: delta.metric,
+            regression_percent: delta.regression_percent(),
+            max_percent,
+        })
+        .collect()
+}
+
+/// Returns the highest avg throughput in mbit/s of all payload sizes of the given test type
+pub(crate) fn best_avg_mbps(result: &SpeedTestResult, test_type: TestType) -> Option<f64> {
+    let mut stats_per_size: BTreeMap<usize, Statistics> = BTreeMap::new();
+    for measurement in result
+        .measurements
+        .iter()
+        .filter(|m| m.test_type == test_type)
+    {
+        stats_per_size
+            .entry(measurement.payload_size)
+            .or_default()
+            .add_throughput(measurement.bytes_per_sec);
+    }
+    stats_per_size
+        .values()
+        .filter_map(Statistics::mean_mbps)
+        .reduce(f64::max)
+}
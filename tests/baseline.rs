@@ -0,0 +1,188 @@
+mod common;
+
+use cfspeedtest::baseline::{compare, load_baseline, BaselineDelta, Metric};
+use cfspeedtest::error::SpeedTestError;
+use cfspeedtest::measurements::write_result;
+use cfspeedtest::speedtest::{SpeedTestResult, TestType};
+use cfspeedtest::thresholds::{check_regressions, ThresholdViolation};
+use cfspeedtest::{OutputFormat, SpeedTestCLIOptions, Unit};
+use common::{measurement, speed_test_result};
+use std::path::PathBuf;
+
+fn result_with_throughput(download_mbit: f64, upload_mbit: f64) -> SpeedTestResult {
+    let mut measurements = Vec::new();
+    for _ in 0..4 {
+        measurements.push(measurement(TestType::Download, 1_000_000, download_mbit));
+        measurements.push(measurement(TestType::Upload, 1_000_000, upload_mbit));
+    }
+    speed_test_result(measurements)
+}
+
+/// Writes the json output of the given runs to a temp file, one line per run
+fn write_baseline(name: &str, runs: &[SpeedTestResult], options: &SpeedTestCLIOptions) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "cfspeedtest-baseline-{name}-{}.json",
+        std::process::id()
+    ));
+    let mut output = Vec::new();
+    for run in runs {
+        write_result(&mut output, run, options, true).unwrap();
+    }
+    std::fs::write(&path, output).unwrap();
+    path
+}
+
+fn json_options() -> SpeedTestCLIOptions {
+    SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn loads_baseline_from_json_output() {
+    let path = write_baseline(
+        "load",
+        &[result_with_throughput(100.0, 20.0)],
+        &json_options(),
+    );
+    let baseline = load_baseline(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(baseline.download_mbps, Some(100.0));
+    assert_eq!(baseline.upload_mbps, Some(20.0));
+    assert_eq!(baseline.latency_ms, Some(11.5));
+}
+
+#[test]
+fn converts_baseline_throughput_from_its_unit() {
+    let options = SpeedTestCLIOptions {
+        unit: Unit::MBytePs,
+        ..json_options()
+    };
+    let path = write_baseline("unit", &[result_with_throughput(100.0, 20.0)], &options);
+    let baseline = load_baseline(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let download_mbps = baseline.download_mbps.unwrap();
+    assert!((download_mbps - 100.0).abs() < 1e-9, "{download_mbps}");
+}
+
+#[test]
+fn uses_the_last_appended_run() {
+    let runs = [
+        result_with_throughput(100.0, 20.0),
+        result_with_throughput(50.0, 10.0),
+    ];
+    let path = write_baseline("append", &runs, &json_options());
+    let baseline = load_baseline(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(baseline.download_mbps, Some(50.0));
+}
+
+#[test]
+fn loads_baseline_with_other_schema_version() {
+    let path = write_baseline(
+        "schema",
+        &[result_with_throughput(100.0, 20.0)],
+        &json_options(),
+    );
+    let json = std::fs::read_to_string(&path)
+        .unwrap()
+        .replace("\"schema_version\":1", "\"schema_version\":0");
+    std::fs::write(&path, json).unwrap();
+    let baseline = load_baseline(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(baseline.unwrap().download_mbps, Some(100.0));
+}
+
+#[test]
+fn fails_for_invalid_baseline() {
+    let path = std::env::temp_dir().join(format!(
+        "cfspeedtest-baseline-invalid-{}.json",
+        std::process::id()
+    ));
+    std::fs::write(&path, "[1, 2, 3]").unwrap();
+    let baseline = load_baseline(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(baseline, Err(SpeedTestError::Baseline(_))));
+}
+
+#[test]
+fn fails_for_missing_baseline() {
+    let path = std::env::temp_dir().join("cfspeedtest-baseline-missing.json");
+
+    match load_baseline(&path) {
+        Err(SpeedTestError::Baseline(message)) => {
+            assert!(
+                message.starts_with(&path.display().to_string()),
+                "{message}"
+            )
+        }
+        other => panic!("expected a baseline error, got {other:?}"),
+    }
+}
+
+#[test]
+fn compares_result_against_baseline() {
+    let path = write_baseline(
+        "compare",
+        &[result_with_throughput(100.0, 20.0)],
+        &json_options(),
+    );
+    let baseline = load_baseline(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let deltas = compare(&result_with_throughput(104.0, 15.0), &baseline);
+    assert_eq!(deltas.len(), 3);
+    assert_eq!(deltas[0].metric, Metric::Download);
+    assert!((deltas[0].delta_percent() - 4.0).abs() < 1e-9);
+    assert_eq!(
+        deltas[0].format(Unit::Mbps),
+        "download: 104.00 mbit/s (+4.0% vs baseline)"
+    );
+    assert!((deltas[1].delta_percent() + 25.0).abs() < 1e-9);
+    assert_eq!(deltas[2].metric, Metric::Latency);
+    assert_eq!(deltas[2].delta_percent(), 0.0);
+}
+
+#[test]
+fn reports_regressions_above_the_maximum() {
+    let deltas = [
+        BaselineDelta {
+            metric: Metric::Download,
+            current: 80.0,
+            baseline: 100.0,
+        },
+        BaselineDelta {
+            metric: Metric::Upload,
+            current: 25.0,
+            baseline: 20.0,
+        },
+        BaselineDelta {
+            metric: Metric::Latency,
+            current: 15.0,
+            baseline: 10.0,
+        },
+    ];
+
+    let violations = check_regressions(&deltas, 10.0);
+    assert_eq!(
+        violations,
+        vec![
+            ThresholdViolation::Regression {
+                metric: Metric::Download,
+                regression_percent: 20.0,
+                max_percent: 10.0
+            },
+            ThresholdViolation::Regression {
+                metric: Metric::Latency,
+                regression_percent: 50.0,
+                max_percent: 10.0
+            },
+        ]
+    );
+}
@@ -0,0 +1,276 @@
+//! Minimal HTTP/1.1 server mimicking the speed.cloudflare.com endpoints used by the speed test
+//! and builders for speed test results
+// not every test binary uses all of the helpers
+#![allow(dead_code)]
+
+use cfspeedtest::measurements::Measurement;
+use cfspeedtest::speedtest::{Metadata, SpeedTestResult, TestType};
+use chrono::{DateTime, Utc};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Request received by the mock server
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub body_len: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct MockConfig {
+    /// Delay before sending the body of a download response or the response to an upload
+    pub delay: Duration,
+    /// Delay before responding to the empty downloads used for latency and packet loss
+    pub empty_delay: Duration,
+    /// Additional delays before sending the body of consecutive downloads, cycled through
+    pub download_delays: Vec<Duration>,
+    /// Server processing time in ms reported in the `Server-Timing` header
+    pub server_timing_ms: f64,
+    pub colo: String,
+    pub city: String,
+    /// Number of download and upload requests answered with `failure_status` before succeeding
+    pub failures: usize,
+    pub failure_status: &'static str,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            delay: Duration::ZERO,
+            empty_delay: Duration::ZERO,
+            download_delays: Vec::new(),
+            server_timing_ms: 0.0,
+            colo: "ZRH".to_string(),
+            city: "Zurich".to_string(),
+            failures: 0,
+            failure_status: "503 Service Unavailable",
+        }
+    }
+}
+
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockServer {
+    /// Starts the server on a random local port, it keeps running until the test process exits
+    pub fn start(config: MockConfig) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind mock server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        let failures = Arc::new(AtomicUsize::new(config.failures));
+        let downloads = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let config = config.clone();
+                let recorded = Arc::clone(&recorded);
+                let failures = Arc::clone(&failures);
+                let downloads = Arc::clone(&downloads);
+                thread::spawn(move || {
+                    handle_connection(stream, &config, &recorded, &failures, &downloads)
+                });
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// Returns all requests received so far
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    config: &MockConfig,
+    recorded: &Mutex<Vec<RecordedRequest>>,
+    failures: &AtomicUsize,
+    downloads: &AtomicUsize,
+) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+    recorded.lock().unwrap().push(RecordedRequest {
+        method: method.clone(),
+        path: path.clone(),
+        body_len: content_length,
+    });
+
+    let is_measurement =
+        (method == "GET" && path.starts_with("/__down?bytes=") && path != "/__down?bytes=0")
+            || (method == "POST" && path == "/__up");
+    let fail = is_measurement
+        && failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+    let _ = match (method.as_str(), path.as_str()) {
+        _ if fail => respond(&mut stream, config.failure_status, b""),
+        ("GET", path) if path.starts_with("/__down?bytes=") => {
+            let bytes: usize = path["/__down?bytes=".len()..].parse().unwrap_or(0);
+            let extra_delay = match config.download_delays.len() {
+                0 => Duration::ZERO,
+                _ if bytes == 0 => Duration::ZERO,
+                len => config.download_delays[downloads.fetch_add(1, Ordering::SeqCst) % len],
+            };
+            respond_download(&mut stream, config, bytes, extra_delay)
+        }
+        ("POST", "/__up") => {
+            thread::sleep(config.delay);
+            respond(&mut stream, "200 OK", b"")
+        }
+        ("GET", "/cdn-cgi/trace") => {
+            let trace = format!("fl=1\nip=127.0.0.1\ncolo={}\nhttp=http/1.1\n", config.colo);
+            respond(&mut stream, "200 OK", trace.as_bytes())
+        }
+        ("GET", path) if path.starts_with("/dns-query?") => {
+            respond(&mut stream, "200 OK", dns_response(path).as_bytes())
+        }
+        ("GET", "/locations") => {
+            let locations = format!(r#"[{{"iata":"{}","city":"{}"}}]"#, config.colo, config.city);
+            respond(&mut stream, "200 OK", locations.as_bytes())
+        }
+        _ => respond(&mut stream, "404 Not Found", b""),
+    };
+}
+
+fn respond_download(
+    stream: &mut TcpStream,
+    config: &MockConfig,
+    bytes: usize,
+    extra_delay: Duration,
+) -> std::io::Result<()> {
+    let server_timing = format!("cfRequestDuration;dur={}", config.server_timing_ms);
+    let headers = [
+        ("Server-Timing", server_timing.as_str()),
+        ("cf-meta-colo", config.colo.as_str()),
+        ("cf-meta-city", config.city.as_str()),
+        ("cf-meta-country", "CH"),
+        ("cf-meta-ip", "127.0.0.1"),
+        ("cf-meta-asn", "13335"),
+    ];
+    if bytes == 0 {
+        thread::sleep(config.empty_delay);
+    }
+    write_head(stream, "200 OK", &headers, bytes)?;
+    if bytes > 0 {
+        thread::sleep(config.delay + extra_delay);
+    }
+    stream.write_all(&vec![0; bytes])?;
+    stream.flush()
+}
+
+/// Answers A lookups with 127.0.0.1 and AAAA lookups with ::1 behind a CNAME in the DoH JSON
+/// format, unknown names are answered with NXDOMAIN
+fn dns_response(path: &str) -> String {
+    let query = &path["/dns-query?".len()..];
+    let param = |key: &str| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(&format!("{key}=")))
+            .unwrap_or_default()
+    };
+    if param("name") != "speed.example.com" {
+        return r#"{"Status":3}"#.to_string();
+    }
+    let (record_type, data) = match param("type") {
+        "A" => (1, "127.0.0.1"),
+        _ => (28, "::1"),
+    };
+    format!(
+        r#"{{"Status":0,"Answer":[{{"name":"speed.example.com","type":5,"TTL":300,"data":"cdn.example.com."}},{{"name":"cdn.example.com","type":{record_type},"TTL":300,"data":"{data}"}}]}}"#
+    )
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &[u8]) -> std::io::Result<()> {
+    write_head(stream, status, &[], body.len())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn write_head(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    content_length: usize,
+) -> std::io::Result<()> {
+    let mut head =
+        format!("HTTP/1.1 {status}\r\nContent-Length: {content_length}\r\nConnection: close\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.flush()
+}
+
+/// Result with the given measurements, an avg latency of 11.5 ms and no truncation
+pub fn speed_test_result(measurements: Vec<Measurement>) -> SpeedTestResult {
+    SpeedTestResult {
+        metadata: Metadata {
+            city: "Zurich".to_string(),
+            country: "CH".to_string(),
+            ip: "127.0.0.1".to_string(),
+            asn: "13335".to_string(),
+            colo: "ZRH".to_string(),
+            http_version: "HTTP/1.1".to_string(),
+        },
+        server_location: Some("Zurich (ZRH)".to_string()),
+        client_ip: Some("127.0.0.1".to_string()),
+        ip_family: None,
+        latency_measurements: latency_samples(&[10.0, 12.0, 11.0, 13.0]),
+        avg_latency: Some(11.5),
+        latency_timestamp: Utc::now(),
+        jitter_ms: Some(1.5),
+        measurements,
+        truncated: false,
+        packet_loss: None,
+    }
+}
+
+/// Latency samples with the given latencies in ms, all completed now
+pub fn latency_samples(latencies: &[f64]) -> Vec<(f64, DateTime<Utc>)> {
+    latencies
+        .iter()
+        .map(|&latency| (latency, Utc::now()))
+        .collect()
+}
+
+/// Measurement with the given throughput in mbit/s and a TTFB of 20 ms
+pub fn measurement(test_type: TestType, payload_size: usize, mbit: f64) -> Measurement {
+    Measurement {
+        test_type,
+        payload_size,
+        bytes_per_sec: mbit * 1_000_000.0 / 8.0,
+        ttfb_ms: Some(20.0),
+        timestamp: Utc::now(),
+    }
+}
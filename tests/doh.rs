@@ -0,0 +1,55 @@
+mod common;
+
+use cfspeedtest::doh::resolve;
+use cfspeedtest::error::SpeedTestError;
+use common::{MockConfig, MockServer};
+use reqwest::blocking::Client;
+use std::net::IpAddr;
+
+const HOST: &str = "speed.example.com";
+
+fn resolver_url(server: &MockServer) -> String {
+    format!("{}/dns-query", server.url)
+}
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+#[test]
+fn resolves_a_and_aaaa_records() {
+    let server = MockServer::start(MockConfig::default());
+    let addresses = resolve(&Client::new(), &resolver_url(&server), HOST, false, false).unwrap();
+
+    assert_eq!(addresses, vec![ip("127.0.0.1"), ip("::1")]);
+}
+
+#[test]
+fn resolves_only_the_forced_address_family() {
+    let server = MockServer::start(MockConfig::default());
+    let url = resolver_url(&server);
+
+    let ipv4 = resolve(&Client::new(), &url, HOST, true, false).unwrap();
+    assert_eq!(ipv4, vec![ip("127.0.0.1")]);
+    let ipv6 = resolve(&Client::new(), &url, HOST, false, true).unwrap();
+    assert_eq!(ipv6, vec![ip("::1")]);
+
+    let types: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+    assert!(types[0].ends_with("type=A"), "{types:?}");
+    assert!(types[1].ends_with("type=AAAA"), "{types:?}");
+}
+
+#[test]
+fn fails_for_unknown_hosts() {
+    let server = MockServer::start(MockConfig::default());
+    let error = resolve(
+        &Client::new(),
+        &resolver_url(&server),
+        "unknown.example.com",
+        true,
+        false,
+    )
+    .unwrap_err();
+
+    assert!(matches!(error, SpeedTestError::Dns(_)), "{error:?}");
+}
@@ -0,0 +1,37 @@
+use cfspeedtest::histogram::{bucket_counts, render_histogram, render_sparkline};
+
+#[test]
+fn counts_samples_in_equal_width_buckets() {
+    // width 10, the max sample is counted in the last bucket
+    let samples = [10.0, 12.0, 25.0, 29.0, 31.0, 40.0];
+    assert_eq!(bucket_counts(&samples, 3), vec![2, 2, 2]);
+    assert_eq!(
+        bucket_counts(&[0.0, 1.0, 1.0, 9.0, 10.0], 5),
+        vec![3, 0, 0, 0, 2]
+    );
+}
+
+#[test]
+fn counts_equal_samples_in_the_first_bucket() {
+    assert_eq!(bucket_counts(&[5.0, 5.0, 5.0], 4), vec![3, 0, 0, 0]);
+}
+
+#[test]
+fn no_buckets_without_samples() {
+    assert!(bucket_counts(&[], 10).is_empty());
+    assert!(bucket_counts(&[1.0], 0).is_empty());
+}
+
+#[test]
+fn scales_sparkline_to_largest_bucket() {
+    assert_eq!(render_sparkline(&[8, 0, 4, 1]), "█ ▄▁");
+    assert_eq!(render_sparkline(&[]), "");
+}
+
+#[test]
+fn renders_histogram_with_labels() {
+    assert_eq!(
+        render_histogram(&[10.0, 12.0, 25.0, 29.0, 31.0, 40.0, 40.0], 3),
+        "[▆▆█] 10.00 .. 40.00"
+    );
+}
@@ -0,0 +1,39 @@
+use cfspeedtest::error::SpeedTestError;
+use cfspeedtest::interface::{interface_address, local_bind_address};
+use cfspeedtest::SpeedTestCLIOptions;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[test]
+fn binds_to_unspecified_address_of_forced_family() {
+    let ipv4 = SpeedTestCLIOptions {
+        ipv4: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        local_bind_address(&ipv4),
+        Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    );
+
+    let ipv6 = SpeedTestCLIOptions {
+        ipv6: true,
+        ..Default::default()
+    };
+    let address = local_bind_address(&ipv6).unwrap();
+    assert_eq!(address, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+    assert!(!address.is_loopback());
+}
+
+#[test]
+fn no_bind_address_by_default() {
+    assert_eq!(local_bind_address(&SpeedTestCLIOptions::default()), None);
+}
+
+#[test]
+fn unknown_interface_lists_available_interfaces() {
+    match interface_address("cfspeedtest-does-not-exist", false, false) {
+        Err(SpeedTestError::Interface(message)) => {
+            assert!(message.contains("available interfaces"), "{message}")
+        }
+        other => panic!("expected interface error, got {other:?}"),
+    }
+}
@@ -0,0 +1,641 @@
+mod common;
+
+use cfspeedtest::measurements::{
+    calc_jitter, format_summary, has_unstable_throughput, write_dual_stack_result, write_result,
+    write_result_to_file, JSON_SCHEMA_VERSION,
+};
+use cfspeedtest::speedtest::{PacketLoss, SpeedTestResult, TestType};
+use cfspeedtest::{IpFamily, OutputFormat, SpeedTestCLIOptions, Unit};
+use chrono::{DateTime, TimeZone, Utc};
+use common::{latency_samples, measurement, speed_test_result};
+use serde_json::Value;
+
+/// Result with one download measurement per given throughput in mbit/s
+fn result_with_downloads(mbits: &[f64]) -> SpeedTestResult {
+    speed_test_result(
+        mbits
+            .iter()
+            .map(|&mbit| measurement(TestType::Download, 1_000_000, mbit))
+            .collect(),
+    )
+}
+
+fn json_output(result: &SpeedTestResult, options: &SpeedTestCLIOptions) -> Value {
+    let mut output = Vec::new();
+    write_result(&mut output, result, options, true).unwrap();
+    serde_json::from_slice(&output).unwrap()
+}
+
+fn download_row(output: &Value) -> &Value {
+    &output["download"]["payload_sizes"][0]
+}
+
+#[test]
+fn aggregates_runs_per_payload_size() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        ..Default::default()
+    };
+    let output = json_output(&result_with_downloads(&[40.0, 10.0, 30.0, 20.0]), &options);
+    let download = download_row(&output);
+
+    assert_eq!(download["payload_size"], 1_000_000);
+    assert_eq!(download["min"], 10.0);
+    assert_eq!(download["max"], 40.0);
+    assert_eq!(download["avg"], 25.0);
+    assert_eq!(download["ttfb_ms"], 20.0);
+    assert_eq!(
+        download["runs"],
+        serde_json::json!([40.0, 10.0, 30.0, 20.0])
+    );
+}
+
+#[test]
+fn reports_payload_sizes_with_less_than_four_runs() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        ..Default::default()
+    };
+    let output = json_output(&result_with_downloads(&[30.0, 10.0, 20.0]), &options);
+    let download = download_row(&output);
+
+    assert_eq!(download["min"], 10.0);
+    assert_eq!(download["median"], 20.0);
+    assert_eq!(download["max"], 30.0);
+    assert_eq!(download["avg"], 20.0);
+    assert_eq!(download["q1"], Value::Null);
+    assert_eq!(download["q3"], Value::Null);
+}
+
+#[test]
+fn csv_output_reports_payload_sizes_cut_short() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Csv,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_result(&mut output, &result_with_downloads(&[50.0]), &options, true).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output
+        .lines()
+        .any(|line| line.starts_with("Download,1000000,50.0,,50.0,,50.0,50.0,")));
+}
+
+#[test]
+fn converts_runs_to_requested_unit() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        unit: Unit::MBytePs,
+        ..Default::default()
+    };
+    let output = json_output(&result_with_downloads(&[8.0, 16.0, 24.0, 32.0]), &options);
+    let download = download_row(&output);
+
+    assert_eq!(output["download"]["unit"], "mbyteps");
+    assert_eq!(download["unit"], "mbyteps");
+    assert_eq!(download["max"], 4.0);
+    assert_eq!(download["runs"], serde_json::json!([1.0, 2.0, 3.0, 4.0]));
+}
+
+#[test]
+fn csv_output_omits_runs() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Csv,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_result(
+        &mut output,
+        &result_with_downloads(&[10.0, 20.0, 30.0, 40.0]),
+        &options,
+        true,
+    )
+    .unwrap();
+    let header = String::from_utf8(output).unwrap();
+    let header = header.lines().next().unwrap();
+
+    assert!(header.starts_with("test_type,payload_size,min"));
+    assert!(!header.contains("runs"));
+}
+
+#[test]
+fn omits_skipped_test_types() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        download_only: true,
+        ..Default::default()
+    };
+    let output = json_output(&result_with_downloads(&[10.0, 20.0, 30.0, 40.0]), &options);
+
+    assert_eq!(output["upload"], Value::Null);
+    assert_eq!(output["latency"]["test_type"], "Latency");
+    assert_eq!(
+        output["download"]["payload_sizes"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn json_output_is_a_versioned_object() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::JsonPretty,
+        ..Default::default()
+    };
+    let output = json_output(&result_with_downloads(&[10.0, 20.0, 30.0, 40.0]), &options);
+
+    assert_eq!(output["schema_version"], JSON_SCHEMA_VERSION);
+    assert_eq!(output["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(output["client_ip"], "127.0.0.1");
+    assert_eq!(output["server_location"], "Zurich (ZRH)");
+    assert_eq!(output["http_version"], "HTTP/1.1");
+    assert_eq!(output["truncated"], false);
+    assert!(output["timestamp"].is_string());
+    assert_eq!(output["latency"]["avg"], 11.5);
+}
+
+#[test]
+fn append_writes_csv_header_only_once() {
+    let path = std::env::temp_dir().join(format!("cfspeedtest-append-{}.csv", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Csv,
+        output_file: Some(path.clone()),
+        append: true,
+        ..Default::default()
+    };
+    let result = result_with_downloads(&[10.0, 20.0, 30.0, 40.0]);
+    write_result_to_file(&result, &options, &path).unwrap();
+    write_result_to_file(&result, &options, &path).unwrap();
+    let csv = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let headers = csv.lines().filter(|l| l.starts_with("test_type")).count();
+    assert_eq!(headers, 1);
+    // latency and download row per run
+    assert_eq!(csv.lines().count(), 5);
+}
+
+#[test]
+fn append_writes_json_pretty_as_one_line_per_run() {
+    let path = std::env::temp_dir().join(format!("cfspeedtest-append-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::JsonPretty,
+        output_file: Some(path.clone()),
+        append: true,
+        ..Default::default()
+    };
+    let result = result_with_downloads(&[10.0, 20.0, 30.0, 40.0]);
+    write_result_to_file(&result, &options, &path).unwrap();
+    write_result_to_file(&result, &options, &path).unwrap();
+    let json = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(json.lines().count(), 2);
+    for line in json.lines() {
+        serde_json::from_str::<Value>(line).expect("line is not valid json");
+    }
+}
+
+#[test]
+fn watch_writes_json_pretty_as_one_line_per_run() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::JsonPretty,
+        watch: Some(60),
+        ..Default::default()
+    };
+    let result = result_with_downloads(&[10.0, 20.0, 30.0, 40.0]);
+    let mut output = Vec::new();
+    write_result(&mut output, &result, &options, true).unwrap();
+    write_result(&mut output, &result, &options, false).unwrap();
+    let json = String::from_utf8(output).unwrap();
+
+    assert_eq!(json.lines().count(), 2);
+    for line in json.lines() {
+        serde_json::from_str::<Value>(line).expect("line is not valid json");
+    }
+}
+
+fn result_with_family(ip_family: IpFamily, mbit: f64) -> SpeedTestResult {
+    SpeedTestResult {
+        ip_family: Some(ip_family),
+        ..result_with_downloads(&[mbit; 4])
+    }
+}
+
+#[test]
+fn dual_stack_json_nests_results_under_the_address_family() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        dual_stack: true,
+        ..Default::default()
+    };
+    let results = [
+        result_with_family(IpFamily::Ipv4, 100.0),
+        result_with_family(IpFamily::Ipv6, 50.0),
+    ];
+    let mut output = Vec::new();
+    write_dual_stack_result(&mut output, &results, &options, true).unwrap();
+    let output: Value = serde_json::from_slice(&output).unwrap();
+
+    assert_eq!(output["schema_version"], JSON_SCHEMA_VERSION);
+    assert_eq!(output["ipv4"]["ip_family"], "ipv4");
+    assert_eq!(download_row(&output["ipv4"])["avg"], 100.0);
+    assert_eq!(output["ipv6"]["ip_family"], "ipv6");
+    assert_eq!(download_row(&output["ipv6"])["avg"], 50.0);
+}
+
+#[test]
+fn dual_stack_json_omits_failed_address_family() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        dual_stack: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_dual_stack_result(
+        &mut output,
+        &[result_with_family(IpFamily::Ipv4, 100.0)],
+        &options,
+        true,
+    )
+    .unwrap();
+    let output: Value = serde_json::from_slice(&output).unwrap();
+
+    assert!(output["ipv4"].is_object());
+    assert_eq!(output["ipv6"], Value::Null);
+}
+
+#[test]
+fn dual_stack_csv_labels_rows_with_the_address_family() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Csv,
+        dual_stack: true,
+        ..Default::default()
+    };
+    let results = [
+        result_with_family(IpFamily::Ipv4, 100.0),
+        result_with_family(IpFamily::Ipv6, 50.0),
+    ];
+    let mut output = Vec::new();
+    write_dual_stack_result(&mut output, &results, &options, true).unwrap();
+    let csv = String::from_utf8(output).unwrap();
+
+    assert_eq!(
+        csv.lines().filter(|l| l.starts_with("test_type")).count(),
+        1
+    );
+    assert_eq!(csv.lines().filter(|l| l.contains(",ipv4,")).count(), 2);
+    assert_eq!(csv.lines().filter(|l| l.contains(",ipv6,")).count(), 2);
+}
+
+#[test]
+fn influx_output_tags_the_address_family() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::InfluxLineProtocol,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_result(
+        &mut output,
+        &result_with_family(IpFamily::Ipv6, 100.0),
+        &options,
+        true,
+    )
+    .unwrap();
+    let lines = String::from_utf8(output).unwrap();
+
+    assert!(lines.starts_with("cfspeedtest_latency,family=ipv6 ms=11.5 "));
+    assert!(lines.contains("cfspeedtest,type=download,size=1MB,family=ipv6 mbps=100 "));
+}
+
+#[test]
+fn summary_has_fixed_keys_and_formatting() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Summary,
+        ..Default::default()
+    };
+    let mut measurements: Vec<_> = [200.0, 210.0, 220.0, 230.0]
+        .iter()
+        .map(|&mbit| measurement(TestType::Download, 1_000_000, mbit))
+        .collect();
+    measurements.extend((0..4).map(|_| measurement(TestType::Upload, 1_000_000, 38.1)));
+    let mut output = Vec::new();
+    write_result(
+        &mut output,
+        &speed_test_result(measurements),
+        &options,
+        true,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "down=215.0 up=38.1 latency=11.5 jitter=1.5\n"
+    );
+}
+
+#[test]
+fn summary_prints_skipped_measurements_as_na() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Summary,
+        unit: Unit::MBytePs,
+        ..Default::default()
+    };
+    let result = result_with_downloads(&[80.0; 4]);
+
+    assert_eq!(
+        format_summary(&result, &options),
+        "down=10.0 up=NA latency=11.5 jitter=1.5"
+    );
+}
+
+#[test]
+fn reports_latency_stats_of_less_than_four_samples() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        ..Default::default()
+    };
+    let result = SpeedTestResult {
+        latency_measurements: latency_samples(&[14.0, 10.0]),
+        avg_latency: Some(12.0),
+        jitter_ms: Some(4.0),
+        ..result_with_downloads(&[10.0, 20.0, 30.0, 40.0])
+    };
+    let latency = &json_output(&result, &options)["latency"];
+
+    assert_eq!(latency["min"], 10.0);
+    assert_eq!(latency["median"], 12.0);
+    assert_eq!(latency["max"], 14.0);
+    assert_eq!(latency["avg"], 12.0);
+    assert_eq!(latency["latency_p50_ms"], 12.0);
+    assert_eq!(latency["latency_p90_ms"], 13.6);
+    assert_eq!(latency["jitter_ms"], 4.0);
+}
+
+#[test]
+fn omits_latency_if_it_was_skipped() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        no_latency: true,
+        ..Default::default()
+    };
+    let result = SpeedTestResult {
+        latency_measurements: vec![],
+        avg_latency: None,
+        jitter_ms: None,
+        ..result_with_downloads(&[10.0, 20.0, 30.0, 40.0])
+    };
+
+    assert_eq!(json_output(&result, &options)["latency"], Value::Null);
+    let summary = format_summary(&result, &options);
+    assert_eq!(summary, "down=25.0 up=NA latency=NA jitter=NA");
+}
+
+#[test]
+fn flags_unstable_throughput_in_json_output() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        max_variation: 10.0,
+        ..Default::default()
+    };
+    let stable = result_with_downloads(&[99.0, 100.0, 101.0, 100.0]);
+    let unstable = result_with_downloads(&[10.0, 100.0, 50.0, 200.0]);
+
+    assert_eq!(json_output(&stable, &options)["unstable"], false);
+    assert_eq!(json_output(&unstable, &options)["unstable"], true);
+    assert!(!has_unstable_throughput(&stable, 10.0));
+    assert!(has_unstable_throughput(&unstable, 10.0));
+}
+
+#[test]
+fn payload_sizes_which_are_not_round_get_distinct_labels() {
+    let result = speed_test_result(vec![
+        measurement(TestType::Download, 1_000_000, 100.0),
+        measurement(TestType::Download, 1_500_000, 150.0),
+        measurement(TestType::Download, 65_536, 50.0),
+    ]);
+    let output = |output_format| {
+        let options = SpeedTestCLIOptions {
+            output_format,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_result(&mut output, &result, &options, true).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    let prometheus = output(OutputFormat::Prometheus);
+    assert!(prometheus.contains("cfspeedtest_download_mbps{payload_size=\"1MB\"} 100\n"));
+    assert!(prometheus.contains("cfspeedtest_download_mbps{payload_size=\"1.5MB\"} 150\n"));
+    assert!(prometheus.contains("cfspeedtest_download_mbps{payload_size=\"65.536KB\"} 50\n"));
+    let influx = output(OutputFormat::InfluxLineProtocol);
+    assert!(influx.contains(",size=1MB mbps=100 "));
+    assert!(influx.contains(",size=1.5MB mbps=150 "));
+    assert!(influx.contains(",size=65.536KB mbps=50 "));
+}
+
+#[test]
+fn jitter_is_mean_absolute_difference_of_consecutive_samples() {
+    // differences 2, 1, 3 and 0
+    assert_eq!(calc_jitter(&[10.0, 12.0, 11.0, 14.0, 14.0]), Some(1.5));
+    assert_eq!(calc_jitter(&[20.0, 10.0]), Some(10.0));
+}
+
+#[test]
+fn jitter_needs_at_least_two_samples() {
+    assert_eq!(calc_jitter(&[]), None);
+    assert_eq!(calc_jitter(&[10.0]), None);
+}
+
+/// 2024-01-01T12:00:00Z plus the given number of seconds
+fn timestamp(secs: i64) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap() + chrono::Duration::seconds(secs)
+}
+
+/// Result with a 1MB download at 100 mbit/s and a 512 bytes upload at 50 mbit/s measured 1s and
+/// 2s after the latency test
+fn result_with_fixed_timestamps() -> SpeedTestResult {
+    let mut download = measurement(TestType::Download, 1_000_000, 100.0);
+    download.timestamp = timestamp(1);
+    let mut upload = measurement(TestType::Upload, 512, 50.0);
+    upload.timestamp = timestamp(2);
+    SpeedTestResult {
+        latency_timestamp: timestamp(0),
+        packet_loss: Some(PacketLoss {
+            attempted: 100,
+            timed_out: 1,
+            failed: 1,
+        }),
+        ..speed_test_result(vec![download, upload])
+    }
+}
+
+fn output_as_string(result: &SpeedTestResult, output_format: OutputFormat) -> String {
+    let options = SpeedTestCLIOptions {
+        output_format,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_result(&mut output, result, &options, true).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn json_output_has_the_timestamp_of_every_run() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Json,
+        ..Default::default()
+    };
+    let result = SpeedTestResult {
+        latency_measurements: vec![(10.0, timestamp(-2)), (12.0, timestamp(-1))],
+        ..result_with_fixed_timestamps()
+    };
+    let output = json_output(&result, &options);
+
+    assert_eq!(output["latency"]["runs"], serde_json::json!([10.0, 12.0]));
+    assert_eq!(
+        output["latency"]["run_timestamps"],
+        serde_json::json!(["2024-01-01T11:59:58Z", "2024-01-01T11:59:59Z"])
+    );
+    assert_eq!(output["latency"]["timestamp"], "2024-01-01T11:59:59Z");
+    assert_eq!(
+        download_row(&output)["run_timestamps"],
+        serde_json::json!(["2024-01-01T12:00:01Z"])
+    );
+}
+
+#[test]
+fn influx_line_protocol_output() {
+    let output = output_as_string(
+        &result_with_fixed_timestamps(),
+        OutputFormat::InfluxLineProtocol,
+    );
+
+    assert_eq!(
+        output,
+        "cfspeedtest_latency ms=11.5 1704110400000000000\n\
+         cfspeedtest_jitter ms=1.5 1704110400000000000\n\
+         cfspeedtest_packet_loss percent=2 1704110400000000000\n\
+         cfspeedtest,type=download,size=1MB mbps=100 1704110401000000000\n\
+         cfspeedtest,type=upload,size=512\\ bytes mbps=50 1704110402000000000\n"
+    );
+}
+
+#[test]
+fn influx_line_protocol_output_of_address_family() {
+    let result = SpeedTestResult {
+        ip_family: Some(IpFamily::Ipv4),
+        packet_loss: None,
+        jitter_ms: None,
+        ..result_with_fixed_timestamps()
+    };
+    let output = output_as_string(&result, OutputFormat::InfluxLineProtocol);
+
+    assert_eq!(
+        output,
+        "cfspeedtest_latency,family=ipv4 ms=11.5 1704110400000000000\n\
+         cfspeedtest,type=download,size=1MB,family=ipv4 mbps=100 1704110401000000000\n\
+         cfspeedtest,type=upload,size=512\\ bytes,family=ipv4 mbps=50 1704110402000000000\n"
+    );
+}
+
+#[test]
+fn prometheus_textfile_output() {
+    let output = output_as_string(&result_with_fixed_timestamps(), OutputFormat::Prometheus);
+
+    assert_eq!(
+        output,
+        "# HELP cfspeedtest_latency_ms Avg GET request latency in ms\n\
+         # TYPE cfspeedtest_latency_ms gauge\n\
+         cfspeedtest_latency_ms 11.5\n\
+         # HELP cfspeedtest_jitter_ms Latency jitter in ms\n\
+         # TYPE cfspeedtest_jitter_ms gauge\n\
+         cfspeedtest_jitter_ms 1.5\n\
+         # HELP cfspeedtest_packet_loss_percent Share of lost requests in percent\n\
+         # TYPE cfspeedtest_packet_loss_percent gauge\n\
+         cfspeedtest_packet_loss_percent 2\n\
+         # HELP cfspeedtest_download_mbps Avg download throughput in mbit/s per payload size\n\
+         # TYPE cfspeedtest_download_mbps gauge\n\
+         cfspeedtest_download_mbps{payload_size=\"1MB\"} 100\n\
+         # HELP cfspeedtest_upload_mbps Avg upload throughput in mbit/s per payload size\n\
+         # TYPE cfspeedtest_upload_mbps gauge\n\
+         cfspeedtest_upload_mbps{payload_size=\"512 bytes\"} 50\n"
+    );
+}
+
+#[test]
+fn prometheus_output_has_no_duplicate_series() {
+    let measurements = [1_000_000, 1_000_001, 1_200_000, 1_500_000, 1_999_999]
+        .iter()
+        .flat_map(|&size| {
+            [
+                measurement(TestType::Download, size, 100.0),
+                measurement(TestType::Download, size, 120.0),
+                measurement(TestType::Upload, size, 50.0),
+            ]
+        })
+        .collect();
+    let output = output_as_string(&speed_test_result(measurements), OutputFormat::Prometheus);
+
+    let series: Vec<&str> = output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.rsplit_once(' ').unwrap().0)
+        .collect();
+    let unique: std::collections::HashSet<&str> = series.iter().copied().collect();
+    // latency, jitter and 5 payload sizes per direction
+    assert_eq!(series.len(), 12);
+    assert_eq!(unique.len(), series.len(), "duplicate series in {output}");
+    // metric names are only described once
+    assert_eq!(
+        output.matches("# TYPE cfspeedtest_download_mbps").count(),
+        1
+    );
+}
+
+#[test]
+fn prometheus_textfile_is_replaced_atomically() {
+    let path = std::env::temp_dir().join(format!("cfspeedtest-{}.prom", std::process::id()));
+    let tmp_path = path.with_file_name(format!("cfspeedtest-{}.prom.tmp", std::process::id()));
+    std::fs::write(&path, "stale\n").unwrap();
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Prometheus,
+        ..Default::default()
+    };
+    let result = result_with_fixed_timestamps();
+    write_result_to_file(&result, &options, &path).unwrap();
+    let textfile = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        textfile,
+        output_as_string(&result, OutputFormat::Prometheus)
+    );
+    assert!(!tmp_path.exists(), "temp file wasn't renamed");
+}
+
+#[test]
+fn dual_stack_summary_labels_the_address_family() {
+    let options = SpeedTestCLIOptions {
+        output_format: OutputFormat::Summary,
+        dual_stack: true,
+        ..Default::default()
+    };
+    let results = [
+        result_with_family(IpFamily::Ipv4, 100.0),
+        result_with_family(IpFamily::Ipv6, 80.0),
+    ];
+    let mut output = Vec::new();
+    write_dual_stack_result(&mut output, &results, &options, true).unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "down=100.0 up=NA latency=11.5 jitter=1.5 family=ipv4\n\
+         down=80.0 up=NA latency=11.5 jitter=1.5 family=ipv6\n"
+    );
+}
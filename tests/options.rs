@@ -0,0 +1,78 @@
+use cfspeedtest::speedtest::MAX_READ_CHUNK_BYTES;
+use cfspeedtest::SpeedTestCLIOptions;
+use std::path::PathBuf;
+
+#[test]
+fn rejects_read_chunk_bytes_out_of_bounds() {
+    for read_chunk_bytes in [0, MAX_READ_CHUNK_BYTES + 1] {
+        let options = SpeedTestCLIOptions {
+            read_chunk_bytes,
+            ..Default::default()
+        };
+        assert!(options.validate().is_err(), "{read_chunk_bytes}");
+    }
+    let options = SpeedTestCLIOptions {
+        read_chunk_bytes: MAX_READ_CHUNK_BYTES,
+        ..Default::default()
+    };
+    assert_eq!(options.validate(), Ok(()));
+}
+
+#[test]
+fn rejects_dual_stack_with_watch_or_baseline() {
+    let watch = SpeedTestCLIOptions {
+        dual_stack: true,
+        watch: Some(60),
+        ..Default::default()
+    };
+    assert!(watch.validate().is_err());
+
+    let baseline = SpeedTestCLIOptions {
+        dual_stack: true,
+        baseline: Some(PathBuf::from("baseline.json")),
+        max_regression: Some(10.0),
+        ..Default::default()
+    };
+    assert!(baseline.validate().is_err());
+}
+
+#[test]
+fn rejects_max_mbps_which_is_not_positive() {
+    for max_mbps in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        let options = SpeedTestCLIOptions {
+            max_mbps: Some(max_mbps),
+            ..Default::default()
+        };
+        assert!(options.validate().is_err(), "{max_mbps}");
+    }
+    let options = SpeedTestCLIOptions {
+        max_mbps: Some(0.5),
+        ..Default::default()
+    };
+    assert_eq!(options.validate(), Ok(()));
+}
+
+#[test]
+fn rejects_negative_or_nan_percentages() {
+    for value in [-1.0, f64::NAN] {
+        let max_variation = SpeedTestCLIOptions {
+            max_variation: value,
+            ..Default::default()
+        };
+        assert!(max_variation.validate().is_err(), "{value}");
+
+        let adaptive_tolerance = SpeedTestCLIOptions {
+            adaptive: true,
+            adaptive_tolerance: value,
+            ..Default::default()
+        };
+        assert!(adaptive_tolerance.validate().is_err(), "{value}");
+    }
+    let options = SpeedTestCLIOptions {
+        max_variation: 0.0,
+        adaptive: true,
+        adaptive_tolerance: 0.0,
+        ..Default::default()
+    };
+    assert_eq!(options.validate(), Ok(()));
+}
@@ -0,0 +1,49 @@
+use cfspeedtest::payload::{RandomPayload, CHUNK_BYTES};
+use std::collections::HashSet;
+use std::io::Read;
+
+fn read_payload(len: usize, seed: Option<u64>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    RandomPayload::new(len, seed)
+        .read_to_end(&mut bytes)
+        .unwrap();
+    bytes
+}
+
+#[test]
+fn yields_exactly_the_requested_number_of_bytes() {
+    assert_eq!(read_payload(0, Some(1)).len(), 0);
+    assert_eq!(read_payload(100_001, Some(1)).len(), 100_001);
+}
+
+#[test]
+fn same_seed_yields_same_bytes() {
+    assert_eq!(read_payload(4096, Some(42)), read_payload(4096, Some(42)));
+    assert_ne!(read_payload(4096, Some(42)), read_payload(4096, Some(43)));
+    assert_ne!(read_payload(4096, None), read_payload(4096, None));
+}
+
+#[test]
+fn bytes_are_not_compressible() {
+    let bytes = read_payload(100_000, Some(7));
+    let distinct: HashSet<u8> = bytes.iter().copied().collect();
+    assert_eq!(distinct.len(), 256);
+    let zeros = bytes.iter().filter(|&&b| b == 0).count();
+    // roughly 1/256 of the bytes are expected to be zero
+    assert!(zeros < 1_000, "{zeros} zero bytes");
+}
+
+#[test]
+fn yields_bounded_chunks() {
+    let mut payload = RandomPayload::new(3 * CHUNK_BYTES + 10, Some(1));
+    let mut buffer = vec![0; 4 * CHUNK_BYTES];
+    let mut chunks = Vec::new();
+    loop {
+        let len = payload.read(&mut buffer).unwrap();
+        if len == 0 {
+            break;
+        }
+        chunks.push(len);
+    }
+    assert_eq!(chunks, vec![CHUNK_BYTES, CHUNK_BYTES, CHUNK_BYTES, 10]);
+}
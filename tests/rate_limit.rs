@@ -0,0 +1,49 @@
+use cfspeedtest::rate_limit::{RateLimitedReader, RateLimiter};
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+#[test]
+fn delays_transfers_ahead_of_the_max_rate() {
+    // 1000 bytes at 1000 bytes/s should take 1s
+    assert_eq!(
+        RateLimiter::delay(1_000.0, 1_000, Duration::from_millis(250)),
+        Duration::from_millis(750)
+    );
+    assert_eq!(
+        RateLimiter::delay(1_000.0, 500, Duration::ZERO),
+        Duration::from_millis(500)
+    );
+}
+
+#[test]
+fn does_not_delay_transfers_behind_the_max_rate() {
+    assert_eq!(
+        RateLimiter::delay(1_000.0, 1_000, Duration::from_secs(2)),
+        Duration::ZERO
+    );
+    assert_eq!(
+        RateLimiter::delay(0.0, 1_000, Duration::ZERO),
+        Duration::ZERO
+    );
+}
+
+#[test]
+fn throttles_reads() {
+    let mut reader = RateLimitedReader::new(
+        io::repeat(1).take(50_000),
+        Some(RateLimiter::new(250_000.0)),
+    );
+    let start = Instant::now();
+    let bytes = io::copy(&mut reader, &mut io::sink()).unwrap();
+
+    assert_eq!(bytes, 50_000);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn passes_reads_through_without_limiter() {
+    let mut reader = RateLimitedReader::new(io::repeat(1).take(10), None);
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).unwrap();
+    assert_eq!(bytes, vec![1; 10]);
+}
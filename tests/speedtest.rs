@@ -0,0 +1,562 @@
+mod common;
+
+use cfspeedtest::progress::ProgressEvent;
+use cfspeedtest::speedtest::{
+    fetch_trace, run_latency_test, run_packet_loss_test, speed_test, speed_test_with_cancellation,
+    speed_test_with_progress, transfer_timeout, CancellationToken, PacketLoss, PayloadSize,
+    TestType, Trace,
+};
+use cfspeedtest::{OutputFormat, SpeedTestCLIOptions};
+use common::{MockConfig, MockServer};
+use reqwest::blocking::Client;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const PAYLOAD_SIZE: usize = 100_000;
+const DELAY: Duration = Duration::from_millis(100);
+
+fn options(server: &MockServer) -> SpeedTestCLIOptions {
+    SpeedTestCLIOptions {
+        server_url: server.url.clone(),
+        output_format: OutputFormat::None,
+        nr_tests: 4,
+        nr_latency_tests: 3,
+        payload_sizes: Some(vec![PayloadSize(PAYLOAD_SIZE)]),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn measures_download_and_upload_against_mock_server() {
+    let server = MockServer::start(MockConfig {
+        delay: DELAY,
+        ..Default::default()
+    });
+    let result = speed_test(Client::new(), options(&server)).expect("speed test failed");
+
+    assert_eq!(result.metadata.colo, "ZRH");
+    assert_eq!(result.metadata.http_version, "HTTP/1.1");
+    assert_eq!(result.server_location.as_deref(), Some("Zurich (ZRH)"));
+    assert_eq!(result.client_ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(result.latency_measurements.len(), 4);
+
+    for test_type in [TestType::Download, TestType::Upload] {
+        let measurements: Vec<_> = result
+            .measurements
+            .iter()
+            .filter(|m| m.test_type == test_type)
+            .collect();
+        assert_eq!(measurements.len(), 4, "{test_type:?}");
+        for measurement in measurements {
+            assert_eq!(measurement.payload_size, PAYLOAD_SIZE);
+            // the injected delay is an upper bound for the measured throughput
+            let max_bytes_per_sec = PAYLOAD_SIZE as f64 / DELAY.as_secs_f64();
+            assert!(
+                measurement.bytes_per_sec > 0.0 && measurement.bytes_per_sec <= max_bytes_per_sec,
+                "{test_type:?} throughput {} exceeds {max_bytes_per_sec}",
+                measurement.bytes_per_sec
+            );
+        }
+    }
+
+    let uploads: Vec<_> = server
+        .requests()
+        .into_iter()
+        .filter(|r| r.method == "POST" && r.path == "/__up")
+        .collect();
+    assert_eq!(uploads.len(), 4);
+    assert!(uploads.iter().all(|r| r.body_len == PAYLOAD_SIZE));
+}
+
+#[test]
+fn latency_only_skips_download_and_upload() {
+    let server = MockServer::start(MockConfig::default());
+    let options = SpeedTestCLIOptions {
+        latency_only: true,
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    assert!(result.measurements.is_empty());
+    assert!(server.requests().iter().all(|r| r.method == "GET"));
+}
+
+#[test]
+fn latency_excludes_server_processing_time() {
+    let server = MockServer::start(MockConfig {
+        server_timing_ms: 10_000.0,
+        ..Default::default()
+    });
+    let (latencies, avg_latency) =
+        run_latency_test(&Client::new(), &server.url, 5, OutputFormat::None)
+            .expect("latency test failed");
+
+    assert_eq!(latencies.len(), 6);
+    // reported server time exceeds the measured duration, so latency is clamped to 0
+    assert_eq!(avg_latency, Some(0.0));
+}
+
+#[test]
+fn reports_progress_of_downloads_and_uploads() {
+    let server = MockServer::start(MockConfig::default());
+    let events: Arc<Mutex<Vec<ProgressEvent>>> = Arc::default();
+    let recorded = Arc::clone(&events);
+    let result = speed_test_with_progress(
+        Client::new(),
+        options(&server),
+        Some(Box::new(move |event| recorded.lock().unwrap().push(event))),
+    )
+    .expect("speed test failed");
+    let events = events.lock().unwrap();
+
+    for test_type in [TestType::Download, TestType::Upload] {
+        let transferred: Vec<usize> = events
+            .iter()
+            .filter(|e| e.test_type == test_type)
+            .inspect(|e| assert_eq!(e.payload_size, PAYLOAD_SIZE))
+            .map(|e| e.bytes_transferred)
+            .collect();
+        assert!(!transferred.is_empty(), "no {test_type:?} progress");
+        assert!(transferred.iter().all(|&bytes| bytes <= PAYLOAD_SIZE));
+        // every measurement ends with the full payload transferred
+        let completed = transferred.iter().filter(|&&b| b == PAYLOAD_SIZE).count();
+        assert_eq!(completed, 4, "{test_type:?}");
+    }
+    assert_eq!(result.measurements.len(), 8);
+}
+
+#[test]
+fn download_only_and_upload_only_skip_the_other_direction() {
+    let server = MockServer::start(MockConfig::default());
+    for (download_only, upload_only, expected) in [
+        (true, false, TestType::Download),
+        (false, true, TestType::Upload),
+    ] {
+        let options = SpeedTestCLIOptions {
+            download_only,
+            upload_only,
+            ..options(&server)
+        };
+        let result = speed_test(Client::new(), options).expect("speed test failed");
+
+        assert_eq!(result.measurements.len(), 4);
+        assert!(result.measurements.iter().all(|m| m.test_type == expected));
+        assert!(!result.latency_measurements.is_empty());
+    }
+}
+
+#[test]
+fn measures_ttfb_of_downloads() {
+    let server = MockServer::start(MockConfig {
+        delay: DELAY,
+        ..Default::default()
+    });
+    let result = speed_test(Client::new(), options(&server)).expect("speed test failed");
+
+    for measurement in &result.measurements {
+        match measurement.test_type {
+            TestType::Download => {
+                let ttfb_ms = measurement.ttfb_ms.expect("download without ttfb");
+                // the body is only sent after the injected delay
+                assert!(ttfb_ms >= DELAY.as_secs_f64() * 1_000.0, "ttfb {ttfb_ms}ms");
+            }
+            _ => assert_eq!(measurement.ttfb_ms, None),
+        }
+    }
+}
+
+#[test]
+fn max_duration_truncates_measurements() {
+    let server = MockServer::start(MockConfig {
+        delay: Duration::from_millis(300),
+        ..Default::default()
+    });
+    let options = SpeedTestCLIOptions {
+        max_duration: Some(1),
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    assert!(result.truncated);
+    assert!(!result.measurements.is_empty());
+    assert!(result.measurements.len() < 8);
+}
+
+#[test]
+fn retries_server_errors() {
+    let server = MockServer::start(MockConfig {
+        failures: 2,
+        ..Default::default()
+    });
+    let options = SpeedTestCLIOptions {
+        retries: 2,
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    assert_eq!(result.measurements.len(), 8);
+}
+
+#[test]
+fn fails_without_retries() {
+    let server = MockServer::start(MockConfig {
+        failures: 1,
+        ..Default::default()
+    });
+    assert!(speed_test(Client::new(), options(&server)).is_err());
+}
+
+#[test]
+fn does_not_retry_client_errors() {
+    let server = MockServer::start(MockConfig {
+        failures: 1,
+        failure_status: "403 Forbidden",
+        ..Default::default()
+    });
+    let options = SpeedTestCLIOptions {
+        retries: 3,
+        ..options(&server)
+    };
+    match speed_test(Client::new(), options) {
+        Err(e) => assert!(!e.is_retryable(), "{e}"),
+        Ok(_) => panic!("expected the 403 response to fail the speed test"),
+    }
+}
+
+#[test]
+fn measure_loss_reports_packet_loss() {
+    let server = MockServer::start(MockConfig::default());
+    let options = SpeedTestCLIOptions {
+        latency_only: true,
+        measure_loss: true,
+        loss_requests: 5,
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    let packet_loss = result.packet_loss.expect("no packet loss measured");
+    assert_eq!(
+        packet_loss,
+        PacketLoss {
+            attempted: 5,
+            timed_out: 0,
+            failed: 0
+        }
+    );
+    assert_eq!(packet_loss.percent(), Some(0.0));
+}
+
+#[test]
+fn packet_loss_is_not_measured_by_default() {
+    let server = MockServer::start(MockConfig::default());
+    let options = SpeedTestCLIOptions {
+        latency_only: true,
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    assert_eq!(result.packet_loss, None);
+}
+
+#[test]
+fn counts_requests_exceeding_the_deadline_as_lost() {
+    let server = MockServer::start(MockConfig {
+        empty_delay: Duration::from_millis(300),
+        ..Default::default()
+    });
+    let packet_loss = run_packet_loss_test(
+        &Client::new(),
+        &server.url,
+        3,
+        Duration::from_millis(50),
+        OutputFormat::None,
+        &CancellationToken::new(),
+    );
+
+    assert_eq!(
+        packet_loss,
+        PacketLoss {
+            attempted: 3,
+            timed_out: 3,
+            failed: 0
+        }
+    );
+    assert_eq!(packet_loss.percent(), Some(100.0));
+}
+
+#[test]
+fn counts_failed_requests_as_lost() {
+    // nothing is listening on the port once the listener is dropped
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let packet_loss = run_packet_loss_test(
+        &Client::new(),
+        &url,
+        4,
+        Duration::from_millis(500),
+        OutputFormat::None,
+        &CancellationToken::new(),
+    );
+
+    assert_eq!(
+        packet_loss,
+        PacketLoss {
+            attempted: 4,
+            timed_out: 0,
+            failed: 4
+        }
+    );
+    assert_eq!(packet_loss.lost(), 4);
+}
+
+#[test]
+fn reads_server_location_and_client_ip_from_a_single_trace_request() {
+    let server = MockServer::start(MockConfig::default());
+    let trace = fetch_trace(&Client::new(), &server.url);
+
+    assert_eq!(trace.server_location.as_deref(), Some("Zurich (ZRH)"));
+    assert_eq!(trace.client_ip.as_deref(), Some("127.0.0.1"));
+    let trace_requests = server
+        .requests()
+        .iter()
+        .filter(|r| r.path == "/cdn-cgi/trace")
+        .count();
+    assert_eq!(trace_requests, 1);
+}
+
+#[test]
+fn trace_is_empty_if_the_request_fails() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    assert_eq!(fetch_trace(&Client::new(), &url), Trace::default());
+}
+
+#[test]
+fn streams_upload_payloads_spanning_multiple_chunks() {
+    // not a multiple of the chunk size to cover the last partial chunk
+    const LARGE_PAYLOAD: usize = 1_234_567;
+    let server = MockServer::start(MockConfig::default());
+    let options = SpeedTestCLIOptions {
+        upload_only: true,
+        payload_sizes: Some(vec![PayloadSize(LARGE_PAYLOAD)]),
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    assert_eq!(result.measurements.len(), 4);
+    let uploads: Vec<_> = server
+        .requests()
+        .into_iter()
+        .filter(|r| r.method == "POST")
+        .collect();
+    assert_eq!(uploads.len(), 4);
+    assert!(uploads.iter().all(|r| r.body_len == LARGE_PAYLOAD));
+}
+
+#[test]
+fn max_mbps_caps_the_throughput() {
+    let server = MockServer::start(MockConfig::default());
+    let max_mbps = 4.0;
+    let options = SpeedTestCLIOptions {
+        download_only: true,
+        max_mbps: Some(max_mbps),
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    assert_eq!(result.measurements.len(), 4);
+    for measurement in &result.measurements {
+        let mbps = measurement.bytes_per_sec * 8.0 / 1_000_000.0;
+        // allow some slack for the timer resolution
+        assert!(mbps <= max_mbps * 1.05, "{mbps} mbit/s exceeds the cap");
+    }
+}
+
+#[test]
+fn scales_transfer_timeout_with_payload_size() {
+    let min_timeout = Duration::from_secs(30);
+    assert_eq!(transfer_timeout(100_000, min_timeout), min_timeout);
+    // 100MB at 1 mbit/s
+    assert_eq!(
+        transfer_timeout(100_000_000, min_timeout),
+        Duration::from_secs(800)
+    );
+}
+
+#[test]
+fn upload_timeout_overrides_client_timeout() {
+    let server = MockServer::start(MockConfig {
+        delay: Duration::from_millis(1_500),
+        ..Default::default()
+    });
+    let client = Client::builder()
+        .timeout(Duration::from_secs(1))
+        .build()
+        .unwrap();
+    let options = SpeedTestCLIOptions {
+        upload_only: true,
+        nr_tests: 1,
+        upload_timeout: Some(3),
+        ..options(&server)
+    };
+    let result = speed_test(client, options).expect("speed test failed");
+
+    assert_eq!(result.measurements.len(), 1);
+}
+
+#[test]
+fn download_timeout_aborts_slow_downloads() {
+    let server = MockServer::start(MockConfig {
+        delay: Duration::from_millis(1_500),
+        ..Default::default()
+    });
+    let options = SpeedTestCLIOptions {
+        download_only: true,
+        nr_tests: 1,
+        download_timeout: Some(1),
+        ..options(&server)
+    };
+
+    assert!(speed_test(Client::new(), options).is_err());
+}
+
+#[test]
+fn no_latency_skips_the_latency_test() {
+    let server = MockServer::start(MockConfig::default());
+    let options = SpeedTestCLIOptions {
+        no_latency: true,
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    assert!(result.latency_measurements.is_empty());
+    assert_eq!(result.jitter_ms, None);
+    assert_eq!(result.measurements.len(), 8);
+    // only the metadata request uses an empty download
+    let empty_downloads = server
+        .requests()
+        .iter()
+        .filter(|r| r.path == "/__down?bytes=0")
+        .count();
+    assert_eq!(empty_downloads, 1);
+}
+
+#[test]
+fn reads_downloads_in_chunks_of_read_chunk_bytes() {
+    let server = MockServer::start(MockConfig::default());
+    let events: Arc<Mutex<Vec<ProgressEvent>>> = Arc::default();
+    let recorded = Arc::clone(&events);
+    let options = SpeedTestCLIOptions {
+        read_chunk_bytes: 1_000,
+        download_only: true,
+        ..options(&server)
+    };
+    speed_test_with_progress(
+        Client::new(),
+        options,
+        Some(Box::new(move |event| recorded.lock().unwrap().push(event))),
+    )
+    .expect("speed test failed");
+    let events = events.lock().unwrap();
+
+    let downloads: Vec<&ProgressEvent> = events
+        .iter()
+        .filter(|e| e.test_type == TestType::Download)
+        .collect();
+    // 4 runs of a 100KB payload need at least 100 reads each
+    assert!(downloads.len() >= 4 * PAYLOAD_SIZE / 1_000);
+    assert!(downloads
+        .windows(2)
+        .filter(|pair| pair[1].bytes_transferred > pair[0].bytes_transferred)
+        .all(|pair| pair[1].bytes_transferred - pair[0].bytes_transferred <= 1_000));
+}
+
+#[test]
+fn cancellation_only_stops_the_speed_test_using_the_token() {
+    let server = MockServer::start(MockConfig::default());
+    let cancellation = CancellationToken::new();
+    cancellation.cancel();
+    let cancelled =
+        speed_test_with_cancellation(Client::new(), options(&server), None, cancellation)
+            .expect("speed test failed");
+    assert!(cancelled.latency_measurements.is_empty());
+    assert!(cancelled.measurements.is_empty());
+
+    // later speed tests aren't affected
+    let result = speed_test(Client::new(), options(&server)).expect("speed test failed");
+    assert_eq!(result.measurements.len(), 8);
+}
+
+#[test]
+fn parallel_throughput_is_total_bytes_over_wall_clock_time() {
+    // the connections of every run complete one after another
+    let server = MockServer::start(MockConfig {
+        download_delays: vec![
+            Duration::ZERO,
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+        ],
+        ..Default::default()
+    });
+    let options = SpeedTestCLIOptions {
+        parallel: 3,
+        download_only: true,
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    assert_eq!(result.measurements.len(), 4);
+    for measurement in &result.measurements {
+        // summing up the rates of the single connections would exceed this by far
+        let max_bytes_per_sec = 3.0 * PAYLOAD_SIZE as f64 / 0.2;
+        assert!(
+            measurement.bytes_per_sec <= max_bytes_per_sec,
+            "{} > {max_bytes_per_sec}",
+            measurement.bytes_per_sec
+        );
+    }
+    let downloads = server
+        .requests()
+        .iter()
+        .filter(|r| r.path == format!("/__down?bytes={PAYLOAD_SIZE}"))
+        .count();
+    assert_eq!(downloads, 12);
+}
+
+#[test]
+fn parallel_skips_failed_connections() {
+    let server = MockServer::start(MockConfig {
+        failures: 2,
+        delay: Duration::from_millis(50),
+        ..Default::default()
+    });
+    let options = SpeedTestCLIOptions {
+        parallel: 3,
+        download_only: true,
+        ..options(&server)
+    };
+    let result = speed_test(Client::new(), options).expect("speed test failed");
+
+    assert_eq!(result.measurements.len(), 4);
+    // 2 of the 3 connections of the first run failed, only the remaining one counts
+    let first = result.measurements[0].bytes_per_sec;
+    assert!(first <= PAYLOAD_SIZE as f64 / 0.05, "{first}");
+}
+
+#[test]
+fn parallel_fails_if_all_connections_failed() {
+    let server = MockServer::start(MockConfig {
+        failures: 3,
+        ..Default::default()
+    });
+    let options = SpeedTestCLIOptions {
+        parallel: 3,
+        download_only: true,
+        ..options(&server)
+    };
+    assert!(speed_test(Client::new(), options).is_err());
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use cfspeedtest::error::SpeedTestError;
+use cfspeedtest::speedtest::{PayloadSize, TestType};
+use cfspeedtest::speedtest_async::speed_test_async;
+use cfspeedtest::{OutputFormat, SpeedTestCLIOptions};
+use common::{MockConfig, MockServer};
+use reqwest::Client;
+use std::time::{Duration, Instant};
+
+const PAYLOAD_SIZE: usize = 100_000;
+
+fn options(server: &MockServer) -> SpeedTestCLIOptions {
+    SpeedTestCLIOptions {
+        server_url: server.url.clone(),
+        output_format: OutputFormat::None,
+        nr_tests: 4,
+        nr_latency_tests: 3,
+        payload_sizes: Some(vec![PayloadSize(PAYLOAD_SIZE)]),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn measures_download_and_upload_against_mock_server() {
+    let server = MockServer::start(MockConfig::default());
+    let result = speed_test_async(Client::new(), options(&server))
+        .await
+        .expect("speed test failed");
+
+    assert_eq!(result.latency_measurements.len(), 4);
+    for test_type in [TestType::Download, TestType::Upload] {
+        let measurements: Vec<_> = result
+            .measurements
+            .iter()
+            .filter(|m| m.test_type == test_type)
+            .collect();
+        assert_eq!(measurements.len(), 4, "{test_type:?}");
+        assert!(measurements.iter().all(|m| m.bytes_per_sec > 0.0));
+    }
+    let uploads: Vec<_> = server
+        .requests()
+        .into_iter()
+        .filter(|r| r.path == "/__up")
+        .collect();
+    assert!(uploads.iter().all(|r| r.body_len == PAYLOAD_SIZE));
+}
+
+#[tokio::test]
+async fn fails_on_server_errors() {
+    let server = MockServer::start(MockConfig {
+        failures: 1,
+        failure_status: "503 Service Unavailable",
+        ..Default::default()
+    });
+
+    match speed_test_async(Client::new(), options(&server)).await {
+        Err(SpeedTestError::Request(e)) => {
+            assert_eq!(e.status(), Some(reqwest::StatusCode::SERVICE_UNAVAILABLE))
+        }
+        other => panic!("expected a server error, got {:?}", other.err()),
+    }
+}
+
+#[tokio::test]
+async fn download_timeout_aborts_slow_downloads() {
+    let server = MockServer::start(MockConfig {
+        delay: Duration::from_millis(1_500),
+        ..Default::default()
+    });
+    let options = SpeedTestCLIOptions {
+        download_only: true,
+        download_timeout: Some(1),
+        ..options(&server)
+    };
+
+    assert!(matches!(
+        speed_test_async(Client::new(), options).await,
+        Err(SpeedTestError::Timeout)
+    ));
+}
+
+#[tokio::test]
+async fn dropping_the_future_aborts_the_speed_test() {
+    let server = MockServer::start(MockConfig {
+        delay: Duration::from_secs(5),
+        ..Default::default()
+    });
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(
+        Duration::from_millis(500),
+        speed_test_async(Client::new(), options(&server)),
+    )
+    .await;
+
+    assert!(outcome.is_err(), "speed test completed");
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn rejects_unsupported_options() {
+    let server = MockServer::start(MockConfig::default());
+    let options = SpeedTestCLIOptions {
+        parallel: 2,
+        max_mbps: Some(10.0),
+        ..options(&server)
+    };
+
+    match speed_test_async(Client::new(), options).await {
+        Err(SpeedTestError::InvalidOptions(message)) => {
+            assert_eq!(
+                message,
+                "--parallel, --max-mbps not supported by the async speed test"
+            )
+        }
+        other => panic!("expected invalid options, got {:?}", other.err()),
+    }
+    // nothing was sent
+    assert!(server.requests().is_empty());
+}
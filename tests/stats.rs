@@ -0,0 +1,160 @@
+use cfspeedtest::stats::{has_converged, is_unstable, percentile, Statistics};
+use std::thread;
+use std::time::Duration;
+
+fn assert_close(actual: Option<f64>, expected: f64) {
+    let actual = actual.expect("no value");
+    assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+}
+
+#[test]
+fn empty_statistics() {
+    let stats = Statistics::new();
+    assert!(stats.is_empty());
+    assert_eq!(stats.mean_mbps(), None);
+    assert_eq!(stats.max_mbps(), None);
+    assert_eq!(stats.min_mbps(), None);
+    assert_eq!(stats.std_dev_mbps(), None);
+}
+
+#[test]
+fn single_sample() {
+    let stats = Statistics::new();
+    // 1MB in 1s = 8 mbit/s
+    stats.add_sample(1_000_000, Duration::from_secs(1));
+    assert_eq!(stats.len(), 1);
+    assert_close(stats.mean_mbps(), 8.0);
+    assert_close(stats.max_mbps(), 8.0);
+    assert_close(stats.min_mbps(), 8.0);
+    assert_close(stats.std_dev_mbps(), 0.0);
+}
+
+#[test]
+fn hand_computed_samples() {
+    let stats = Statistics::new();
+    // 2, 4, 4, 4, 5, 5, 7 and 9 mbit/s, mean 5 and population std dev 2
+    for mbit in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+        stats.add_sample(mbit as usize * 125_000, Duration::from_secs(1));
+    }
+    assert_close(stats.mean_mbps(), 5.0);
+    assert_close(stats.max_mbps(), 9.0);
+    assert_close(stats.min_mbps(), 2.0);
+    assert_close(stats.std_dev_mbps(), 2.0);
+}
+
+#[test]
+fn order_statistics_of_samples_in_any_unit() {
+    let stats = Statistics::from_samples(vec![40.0, 10.0, 30.0, 20.0, 50.0]);
+    assert_eq!(stats.min(), Some(10.0));
+    assert_eq!(stats.max(), Some(50.0));
+    assert_eq!(stats.median(), Some(30.0));
+    assert_eq!(stats.mean(), Some(30.0));
+    // the median belongs to the lower half of an odd number of samples
+    assert_eq!(stats.quartiles(), Some((20.0, 45.0)));
+
+    let even = Statistics::from_samples(vec![4.0, 1.0, 3.0, 2.0]);
+    assert_eq!(even.median(), Some(2.5));
+    assert_eq!(even.quartiles(), Some((1.5, 3.5)));
+}
+
+#[test]
+fn quartiles_need_at_least_four_samples() {
+    let stats = Statistics::from_samples(vec![1.0, 2.0, 3.0]);
+    assert_eq!(stats.median(), Some(2.0));
+    assert_eq!(stats.quartiles(), None);
+    assert_eq!(Statistics::new().median(), None);
+}
+
+#[test]
+fn samples_with_different_durations() {
+    let stats = Statistics::new();
+    stats.add_sample(1_000_000, Duration::from_millis(500));
+    stats.add_sample(1_000_000, Duration::from_secs(2));
+    // 16 and 4 mbit/s
+    assert_close(stats.mean_mbps(), 10.0);
+    assert_close(stats.std_dev_mbps(), 6.0);
+}
+
+#[test]
+fn concurrent_samples() {
+    let stats = Statistics::new();
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..100 {
+                    stats.add_sample(125_000, Duration::from_secs(1));
+                }
+            });
+        }
+    });
+    assert_eq!(stats.len(), 400);
+    assert_close(stats.mean_mbps(), 1.0);
+}
+
+fn stats_of(mbits: &[f64]) -> Statistics {
+    let stats = Statistics::new();
+    for mbit in mbits {
+        stats.add_throughput(mbit * 125_000.0);
+    }
+    stats
+}
+
+#[test]
+fn unstable_if_std_dev_exceeds_share_of_mean() {
+    // mean 5 and population std dev 2
+    let stats = stats_of(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+    assert!(is_unstable(&stats, 0.3));
+    assert!(!is_unstable(&stats, 0.4));
+}
+
+#[test]
+fn too_few_samples_are_never_unstable() {
+    assert!(!is_unstable(&Statistics::new(), 0.0));
+    assert!(!is_unstable(&stats_of(&[10.0]), 0.0));
+}
+
+#[test]
+fn percentile_of_empty_and_single_sample() {
+    assert_eq!(percentile(&[], 50.0), None);
+    assert_eq!(percentile(&[7.0], 0.0), Some(7.0));
+    assert_eq!(percentile(&[7.0], 99.0), Some(7.0));
+}
+
+#[test]
+fn percentile_bounds_are_min_and_max() {
+    let samples = [30.0, 10.0, 50.0, 20.0, 40.0];
+    assert_eq!(percentile(&samples, 0.0), Some(10.0));
+    assert_eq!(percentile(&samples, 100.0), Some(50.0));
+    // out of range percentiles are clamped
+    assert_eq!(percentile(&samples, 150.0), Some(50.0));
+}
+
+#[test]
+fn percentile_interpolates_between_samples() {
+    assert_eq!(percentile(&[20.0, 10.0], 50.0), Some(15.0));
+    assert_eq!(percentile(&[10.0, 20.0], 25.0), Some(12.5));
+    assert_eq!(
+        percentile(&[10.0, 20.0, 30.0, 40.0, 50.0], 90.0),
+        Some(46.0)
+    );
+}
+
+#[test]
+fn converges_if_last_values_are_within_tolerance() {
+    // the first value is outside the window
+    assert!(has_converged(&[10.0, 100.0, 102.0], 2, 0.05));
+    assert!(has_converged(&[100.0, 100.0, 100.0], 3, 0.0));
+}
+
+#[test]
+fn does_not_converge_outside_tolerance() {
+    assert!(!has_converged(&[100.0, 120.0], 2, 0.05));
+    assert!(!has_converged(&[100.0, 102.0, 130.0], 2, 0.05));
+}
+
+#[test]
+fn does_not_converge_without_enough_values_or_a_zero_avg() {
+    assert!(!has_converged(&[100.0], 2, 0.05));
+    assert!(!has_converged(&[100.0, 100.0], 0, 0.05));
+    assert!(!has_converged(&[0.0, 0.0], 2, 0.05));
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use cfspeedtest::speedtest::TestType;
+use cfspeedtest::thresholds::{check_thresholds, ThresholdViolation, Thresholds};
+use common::{measurement, speed_test_result};
+
+#[test]
+fn no_violations_without_thresholds() {
+    let result = speed_test_result(vec![measurement(TestType::Download, 1_000_000, 1.0)]);
+    assert!(check_thresholds(&result, &Thresholds::default()).is_empty());
+}
+
+#[test]
+fn compares_best_avg_throughput_of_all_payload_sizes() {
+    // avg 15 mbit/s for 100kB and 45 mbit/s for 1MB
+    let result = speed_test_result(vec![
+        measurement(TestType::Download, 100_000, 10.0),
+        measurement(TestType::Download, 100_000, 20.0),
+        measurement(TestType::Download, 1_000_000, 40.0),
+        measurement(TestType::Download, 1_000_000, 50.0),
+        measurement(TestType::Upload, 1_000_000, 5.0),
+    ]);
+    let thresholds = Thresholds {
+        min_download_mbps: Some(45.0),
+        min_upload_mbps: Some(10.0),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        check_thresholds(&result, &thresholds),
+        vec![ThresholdViolation::Upload {
+            measured_mbps: 5.0,
+            min_mbps: 10.0
+        }]
+    );
+}
+
+#[test]
+fn reports_latency_above_maximum() {
+    let result = speed_test_result(vec![]);
+    let thresholds = Thresholds {
+        max_latency_ms: Some(10.0),
+        ..Default::default()
+    };
+    let violations = check_thresholds(&result, &thresholds);
+
+    assert_eq!(
+        violations,
+        vec![ThresholdViolation::Latency {
+            measured_ms: 11.5,
+            max_ms: 10.0
+        }]
+    );
+    assert_eq!(
+        violations[0].to_string(),
+        "latency 11.50 ms is above the maximum of 10.00 ms"
+    );
+}
+
+#[test]
+fn ignores_thresholds_of_skipped_test_types() {
+    let result = speed_test_result(vec![measurement(TestType::Download, 1_000_000, 100.0)]);
+    let thresholds = Thresholds {
+        min_upload_mbps: Some(10.0),
+        ..Default::default()
+    };
+    assert!(check_thresholds(&result, &thresholds).is_empty());
+}
@@ -0,0 +1,30 @@
+use cfspeedtest::Unit;
+
+// 125MB/s = 1000 mbit/s = 1 gbit/s
+const BYTES_PER_SEC: f64 = 125_000_000.0;
+
+#[test]
+fn converts_bytes_per_sec_to_every_unit() {
+    assert_eq!(Unit::Mbps.convert(BYTES_PER_SEC), 1_000.0);
+    assert_eq!(Unit::MBytePs.convert(BYTES_PER_SEC), 125.0);
+    assert_eq!(Unit::Gbps.convert(BYTES_PER_SEC), 1.0);
+}
+
+#[test]
+fn converts_back_to_bytes_per_sec() {
+    for unit in [Unit::Mbps, Unit::MBytePs, Unit::Gbps] {
+        let value = unit.convert(BYTES_PER_SEC);
+        let bytes_per_sec = unit.to_bytes_per_sec(value);
+        assert!(
+            (bytes_per_sec - BYTES_PER_SEC).abs() < 1e-3,
+            "{unit:?}: {bytes_per_sec}"
+        );
+    }
+}
+
+#[test]
+fn formats_with_the_unit_label() {
+    assert_eq!(Unit::Mbps.format(BYTES_PER_SEC), "1000.00 mbit/s");
+    assert_eq!(Unit::MBytePs.format(BYTES_PER_SEC), "125.00 MB/s");
+    assert_eq!(Unit::Gbps.format(BYTES_PER_SEC), "1.00 gbit/s");
+}